@@ -0,0 +1,183 @@
+/// Joins the lines of records spanning multiple lines, like pretty-printed
+/// JSON, by tracking the depth of the braces and brackets.
+#[derive(Debug)]
+pub struct Assembler {
+    // lines of the record read so far
+    buf: String,
+    depth: usize,
+    in_str: bool,
+    escaped: bool,
+    // records larger than this many bytes are given up on
+    limit: usize,
+}
+
+/// What to do with a line pushed to the [`Assembler`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Push {
+    /// The line is not part of a multi-line record; print it by itself.
+    Line,
+    /// The line was kept until the rest of the record is read.
+    Pending,
+    /// The record is complete, or it grew over the limit and should be
+    /// printed as it is.
+    Record(String),
+    /// The line can't continue a record, like `Starting server` after a
+    /// `config: {`; the kept lines should be printed by themselves, and the
+    /// line pushed again.
+    Abandoned(String),
+}
+
+impl Assembler {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buf: String::new(),
+            depth: 0,
+            in_str: false,
+            escaped: false,
+            limit,
+        }
+    }
+
+    /// Returns `true` if some lines are kept for an unfinished record.
+    pub fn is_pending(&self) -> bool { !self.buf.is_empty() }
+
+    /// Adds the line to the unfinished record, or starts a new one if the
+    /// line opens a `{` or `[` that's not closed in the same line.
+    pub fn push(&mut self, line: &str) -> Push {
+        if self.buf.is_empty() && !line.trim_start().starts_with(['{', '[']) {
+            return Push::Line;
+        }
+
+        if !self.buf.is_empty() && !self.in_str && !continues_record(line) {
+            return Push::Abandoned(self.take().unwrap_or_default());
+        }
+
+        self.scan(line);
+
+        if self.buf.is_empty() && self.depth == 0 {
+            self.reset();
+            return Push::Line;
+        }
+
+        self.buf.push_str(line);
+        if !self.buf.ends_with('\n') {
+            self.buf.push('\n');
+        }
+
+        if self.depth == 0 || self.buf.len() > self.limit {
+            return Push::Record(self.take().unwrap_or_default());
+        }
+
+        Push::Pending
+    }
+
+    /// Takes the lines of the unfinished record, like at the end of a file.
+    pub fn take(&mut self) -> Option<String> {
+        self.reset();
+        self.is_pending().then(|| std::mem::take(&mut self.buf))
+    }
+
+    fn reset(&mut self) {
+        self.depth = 0;
+        self.in_str = false;
+        self.escaped = false;
+    }
+
+    // update the depth by the brackets outside of strings; ANSI escape codes
+    // like `\x1b[31m` are skipped
+    fn scan(&mut self, line: &str) {
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if self.in_str {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_str = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match c {
+                '"' => self.in_str = true,
+                '{' | '[' => self.depth += 1,
+                '}' | ']' => self.depth = self.depth.saturating_sub(1),
+                '\x1b' if chars.next_if_eq(&'[').is_some() => {
+                    while chars.next_if(|c| !('@'..='~').contains(c)).is_some() {}
+                    chars.next();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// whether the line may be a part of pretty-printed JSON, which starts with a
+// key, a value or a bracket
+fn continues_record(line: &str) -> bool {
+    let mut rest = line.trim_start();
+    // skip the colors of tools like `jq -C`
+    while let Some(code) = rest.strip_prefix("\x1b[") {
+        let end = code
+            .find(|c| ('@'..='~').contains(&c))
+            .map_or(code.len(), |i| i + 1);
+        rest = code[end..].trim_start();
+    }
+
+    rest.is_empty()
+        || rest.starts_with(['"', '{', '}', '[', ']', ',', ':', '-'])
+        || rest.starts_with(|c: char| c.is_ascii_digit())
+        || ["true", "false", "null"]
+            .iter()
+            .any(|value| rest.starts_with(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble() {
+        let mut assembler = Assembler::new(1024);
+
+        assert_eq!(assembler.push("plain text {\n"), Push::Line);
+        assert_eq!(assembler.push("{\"a\": 1}\n"), Push::Line);
+        assert_eq!(assembler.push("{\n"), Push::Pending);
+        assert_eq!(
+            assembler.push("  \"msg\": \"} ] \\\" {\",\n"),
+            Push::Pending
+        );
+        assert_eq!(assembler.push("  \"tags\": [\"a\"]\n"), Push::Pending);
+        assert_eq!(
+            assembler.push("}\n"),
+            Push::Record("{\n  \"msg\": \"} ] \\\" {\",\n  \"tags\": [\"a\"]\n}\n".to_owned())
+        );
+        assert!(!assembler.is_pending());
+
+        let mut assembler = Assembler::new(8);
+        assert_eq!(assembler.push("{\n"), Push::Pending);
+        assert_eq!(
+            assembler.push("  \"a\": 1,\n"),
+            Push::Record("{\n  \"a\": 1,\n".to_owned())
+        );
+        assert_eq!(assembler.push("}\n"), Push::Line);
+
+        assert_eq!(assembler.push("[\n"), Push::Pending);
+        assert_eq!(assembler.take(), Some("[\n".to_owned()));
+        assert_eq!(assembler.take(), None);
+
+        let mut assembler = Assembler::new(1024);
+        assert_eq!(assembler.push("[main] config: {\n"), Push::Pending);
+        assert_eq!(
+            assembler.push("\x1b[1;34m\"port\"\x1b[0m: 80,\n"),
+            Push::Pending
+        );
+        assert_eq!(
+            assembler.push("Starting server\n"),
+            Push::Abandoned("[main] config: {\n\x1b[1;34m\"port\"\x1b[0m: 80,\n".to_owned())
+        );
+        assert!(!assembler.is_pending());
+        assert_eq!(assembler.push("Starting server\n"), Push::Line);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt, fs, path::PathBuf};
+use std::{fmt, fs, marker::PhantomData, path::PathBuf};
 
 use etcetera::{choose_base_strategy, BaseStrategy};
 use serde::Deserialize;
@@ -38,26 +38,47 @@ pub struct ConfigFile {
     pub config: Config,
     #[serde(default, deserialize_with = "de_map_to_list")]
     pub variables: Option<Vec<(String, String)>>,
+    #[serde(default)]
+    pub levels: LevelsConfig,
+    #[serde(default, deserialize_with = "de_map_to_list")]
+    pub profiles: Option<Vec<(String, Profile)>>,
+}
+
+/// Config and variables layered over the base ones; the `[profiles.<name>]`
+/// sections.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// expression selecting the profile by the first record, like
+    /// `service == "api"`
+    #[serde(rename = "match")]
+    pub matcher: Option<String>,
+    #[serde(default)]
+    pub config: Config,
+    #[serde(default, deserialize_with = "de_map_to_list")]
+    pub variables: Option<Vec<(String, String)>>,
 }
 
-fn de_map_to_list<'de, D>(de: D) -> Result<Option<Vec<(String, String)>>, D::Error>
+// deserialize a table into a list of its entries, keeping their order
+fn de_map_to_list<'de, D, V>(de: D) -> Result<Option<Vec<(String, V)>>, D::Error>
 where
     D: serde::Deserializer<'de>,
+    V: Deserialize<'de>,
 {
-    struct Visitor;
+    struct Visitor<V>(PhantomData<V>);
 
-    impl<'de> serde::de::Visitor<'de> for Visitor {
-        type Value = Option<Vec<(String, String)>>;
+    impl<'de, V: Deserialize<'de>> serde::de::Visitor<'de> for Visitor<V> {
+        type Value = Option<Vec<(String, V)>>;
 
         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            write!(formatter, "A hex encoded OpId")
+            write!(formatter, "a table")
         }
 
         fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
         where
             D: serde::Deserializer<'de>,
         {
-            deserializer.deserialize_map(Visitor)
+            deserializer.deserialize_map(self)
         }
 
         fn visit_none<E>(self) -> Result<Self::Value, E>
@@ -81,54 +102,124 @@ where
         }
     }
 
-    de.deserialize_any(Visitor)
+    de.deserialize_any(Visitor(PhantomData))
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct Config {
     pub format: Option<String>,
     pub compact: Option<bool>,
     pub no_color: Option<bool>,
     pub strict: Option<bool>,
+    pub expand_nested: Option<bool>,
+    pub attach_lines: Option<bool>,
+    pub style_lines: Option<bool>,
+}
+
+/// Level names and how they are displayed; the `[levels]` section.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelsConfig {
+    pub trace: Option<LevelConfig>,
+    pub debug: Option<LevelConfig>,
+    pub info: Option<LevelConfig>,
+    pub warn: Option<LevelConfig>,
+    pub error: Option<LevelConfig>,
+    pub fatal: Option<LevelConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelConfig {
+    /// additional names of the level, matched case-insensitively
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// inclusive range of numeric levels, like `[30, 39]`
+    pub range: Option<[f64; 2]>,
+    /// text to display instead of the original level
+    pub label: Option<String>,
+    /// styles of the level, like `"red,bold"`
+    pub style: Option<String>,
 }
 
 impl ConfigFile {
     fn merge(&mut self, other: Self) {
-        let Self { config, variables } = self;
+        let Self {
+            config,
+            variables,
+            levels,
+            profiles,
+        } = self;
         let Self {
             config: config2,
             variables: variables2,
+            levels: levels2,
+            profiles: profiles2,
         } = other;
 
-        if let Some(format) = config2.format {
-            config.format = Some(format);
+        config.merge(config2);
+        merge_entries(variables, variables2);
+        merge_entries(profiles, profiles2);
+
+        for (level, level2) in [
+            (&mut levels.trace, levels2.trace),
+            (&mut levels.debug, levels2.debug),
+            (&mut levels.info, levels2.info),
+            (&mut levels.warn, levels2.warn),
+            (&mut levels.error, levels2.error),
+            (&mut levels.fatal, levels2.fatal),
+        ] {
+            if let Some(level2) = level2 {
+                *level = Some(level2);
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Overrides the values that are set in `other`.
+    pub fn merge(&mut self, other: Self) {
+        if let Some(format) = other.format {
+            self.format = Some(format);
+        }
+        if let Some(compact) = other.compact {
+            self.compact = Some(compact);
+        }
+        if let Some(no_color) = other.no_color {
+            self.no_color = Some(no_color);
+        }
+        if let Some(strict) = other.strict {
+            self.strict = Some(strict);
         }
-        if let Some(compact) = config2.compact {
-            config.compact = Some(compact);
+        if let Some(expand_nested) = other.expand_nested {
+            self.expand_nested = Some(expand_nested);
         }
-        if let Some(no_color) = config2.no_color {
-            config.no_color = Some(no_color);
+        if let Some(attach_lines) = other.attach_lines {
+            self.attach_lines = Some(attach_lines);
         }
-        if let Some(strict) = config2.strict {
-            config.strict = Some(strict);
+        if let Some(style_lines) = other.style_lines {
+            self.style_lines = Some(style_lines);
         }
+    }
+}
+
+/// Overrides the entries that are set in `other`, and adds the new ones.
+pub fn merge_entries<V>(list: &mut Option<Vec<(String, V)>>, other: Option<Vec<(String, V)>>) {
+    match (list, other) {
+        (_, None) => (),
+        (v1, Some(v2)) => {
+            if let Some(v1) = v1 {
+                for (k2, v2) in v2 {
+                    let v = v1.iter_mut().find_map(|(k, v)| (k == &k2).then_some(v));
 
-        match (variables, variables2) {
-            (_, None) => (),
-            (v1, Some(v2)) => {
-                if let Some(v1) = v1 {
-                    for (k2, v2) in v2 {
-                        let v = v1.iter_mut().find_map(|(k, v)| (k == &k2).then_some(v));
-
-                        if let Some(v) = v {
-                            *v = v2;
-                        } else {
-                            v1.push((k2, v2));
-                        }
+                    if let Some(v) = v {
+                        *v = v2;
+                    } else {
+                        v1.push((k2, v2));
                     }
-                } else {
-                    *v1 = Some(v2);
                 }
+            } else {
+                *v1 = Some(v2);
             }
         }
     }
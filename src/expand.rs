@@ -132,7 +132,16 @@ fn write_cond(
     variables: &[(String, String)],
 ) -> fmt::Result {
     // '#' means param is a conditional
-    let (content, cond) = if let Some(content) = content.strip_prefix("key ") {
+    let (content, cond) = if let Some(content) = content.strip_prefix("each ") {
+        // the name of the items is written as is
+        let (content, alias) = content.split_once(" as ").unwrap_or((content, ""));
+        f.write_str("{#each ")?;
+        write_field(f, content, variables)?;
+        if !alias.is_empty() {
+            f.write_fmt(format_args!(" as {alias}"))?;
+        }
+        return f.write_char('}');
+    } else if let Some(content) = content.strip_prefix("key ") {
         (content, "key")
     } else if let Some(content) = content.strip_prefix("if ") {
         (content, "if")
@@ -143,7 +152,11 @@ fn write_cond(
     };
 
     f.write_fmt(format_args!("{{#{cond} "))?;
-    write_field(f, content, variables)?;
+    if cond == "if" {
+        write_cond_expr(f, content, variables)?;
+    } else {
+        write_field(f, content, variables)?;
+    }
     f.write_char('}')?;
 
     Ok(())
@@ -163,7 +176,7 @@ fn write_cond_else(
         f.write_char('}')?;
     } else if let Some(content) = content.strip_prefix("else if ") {
         f.write_fmt(format_args!("{{:else if "))?;
-        write_field(f, content, variables)?;
+        write_cond_expr(f, content, variables)?;
         f.write_char('}')?;
     } else {
         panic!("Unsupported conditional :{content}");
@@ -172,6 +185,59 @@ fn write_cond_else(
     Ok(())
 }
 
+// Writes the condition of `{#if}`, like `&level == "ERROR" && retry`,
+// expanding the variables in its fields.
+fn write_cond_expr(
+    f: &mut fmt::Formatter<'_>,
+    content: &str,
+    variables: &[(String, String)],
+) -> fmt::Result {
+    let mut rest = content;
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '"' || c == '\'' {
+            // quoted strings are written as is
+            rest[1..].find(c).map_or(rest.len(), |i| i + 2)
+        } else if rest.starts_with("&&") || rest.starts_with("||") {
+            2
+        } else if c.is_whitespace() || "()=!<>".contains(c) {
+            c.len_utf8()
+        } else {
+            let len = field_len(rest);
+            write_field(f, &rest[..len], variables)?;
+            rest = &rest[len..];
+            continue;
+        };
+
+        f.write_str(&rest[..len])?;
+        rest = &rest[len..];
+    }
+
+    Ok(())
+}
+
+// length of the field at the start of a condition, which ends at whitespace,
+// an operator or a quote outside of brackets
+fn field_len(input: &str) -> usize {
+    let mut in_bracket = false;
+
+    for (i, c) in input.char_indices() {
+        if in_bracket {
+            in_bracket = c != ']';
+        } else if c == '[' {
+            in_bracket = true;
+        } else if c.is_whitespace()
+            || "()=!<>\"'".contains(c)
+            || input[i..].starts_with("&&")
+            || input[i..].starts_with("||")
+        {
+            return i;
+        }
+    }
+
+    input.len()
+}
+
 fn write_arg(
     f: &mut fmt::Formatter<'_>,
     content: &str,
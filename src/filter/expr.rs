@@ -0,0 +1,464 @@
+use std::{borrow::Cow, cmp::Ordering, iter::Peekable, str::CharIndices};
+
+use regex::Regex;
+use thiserror::Error;
+
+use crate::{
+    format::{parse::FormatError, FieldPath},
+    Json,
+};
+
+/// A predicate over the fields of a record, like
+/// `data.user_id == 3175 and status >= 500`.
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(FieldPath),
+    Compare(FieldPath, Op, Literal),
+    Matches(FieldPath, Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Number(f64),
+    // `true`, `false` and `null`
+    Keyword(&'static str),
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, ExprError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, i: 0 };
+
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.i) {
+            return Err(ExprError::UnexpectedToken(token.to_string()));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn matches(&self, json: &Json) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(json) && b.matches(json),
+            Expr::Or(a, b) => a.matches(json) || b.matches(json),
+            Expr::Not(e) => !e.matches(json),
+            Expr::Exists(field) => !field.resolve(json).is_null(),
+            Expr::Compare(field, op, literal) => compare(&field.resolve(json), *op, literal),
+            Expr::Matches(field, regex) => {
+                text(&field.resolve(json)).is_some_and(|t| regex.is_match(&t))
+            }
+        }
+    }
+}
+
+pub(crate) fn compare(json: &Json, op: Op, literal: &Literal) -> bool {
+    if let Literal::Keyword("null") = literal {
+        return match op {
+            Op::Eq => json.is_null(),
+            Op::Ne => !json.is_null(),
+            _ => false,
+        };
+    }
+
+    if json.is_null() {
+        return op == Op::Ne;
+    }
+
+    if op == Op::Contains {
+        if let Some(arr) = json.as_array() {
+            return arr.iter().any(|e| compare(e, Op::Eq, literal));
+        }
+    }
+
+    let Some(text) = text(json) else {
+        return op == Op::Ne;
+    };
+
+    match op {
+        Op::Eq => equals(&text, literal),
+        Op::Ne => !equals(&text, literal),
+        Op::Contains => text.contains(literal.as_str().as_ref()),
+        Op::Lt => ordering(&text, literal) == Some(Ordering::Less),
+        Op::Le => matches!(
+            ordering(&text, literal),
+            Some(Ordering::Less | Ordering::Equal)
+        ),
+        Op::Gt => ordering(&text, literal) == Some(Ordering::Greater),
+        Op::Ge => matches!(
+            ordering(&text, literal),
+            Some(Ordering::Greater | Ordering::Equal)
+        ),
+    }
+}
+
+// text of a string, with its escapes decoded, or of a primitive value;
+// objects and arrays have none
+fn text<'a>(json: &'a Json) -> Option<Cow<'a, str>> {
+    json.as_unescaped_str()
+        .or_else(|| json.as_value().map(Cow::Borrowed))
+}
+
+fn equals(text: &str, literal: &Literal) -> bool {
+    match literal {
+        Literal::Number(n) => text.parse::<f64>().is_ok_and(|v| v == *n),
+        Literal::String(s) => text == s,
+        Literal::Keyword(k) => text == *k,
+    }
+}
+
+// numbers are compared numerically, everything else lexicographically
+fn ordering(text: &str, literal: &Literal) -> Option<Ordering> {
+    match literal {
+        Literal::Number(n) => text.parse::<f64>().ok()?.partial_cmp(n),
+        Literal::String(s) => Some(text.cmp(s.as_str())),
+        Literal::Keyword(k) => Some(text.cmp(k)),
+    }
+}
+
+impl Literal {
+    fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            Literal::String(s) => Cow::Borrowed(s),
+            Literal::Number(n) => Cow::Owned(n.to_string()),
+            Literal::Keyword(k) => Cow::Borrowed(k),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    LParen,
+    RParen,
+    Op(Op),
+    And,
+    Or,
+    Not,
+    // quoted string
+    Str(String),
+    // field path, number, or keyword
+    Word(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+            Token::Op(op) => f.write_str(match op {
+                Op::Eq => "==",
+                Op::Ne => "!=",
+                Op::Lt => "<",
+                Op::Le => "<=",
+                Op::Gt => ">",
+                Op::Ge => ">=",
+                Op::Contains => "contains",
+            }),
+            Token::And => f.write_str("and"),
+            Token::Or => f.write_str("or"),
+            Token::Not => f.write_str("not"),
+            Token::Str(s) => write!(f, "\"{s}\""),
+            Token::Word(w) => f.write_str(w),
+        }
+    }
+}
+
+pub(crate) fn tokenize(input: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                Token::LParen
+            }
+            ')' => {
+                chars.next();
+                Token::RParen
+            }
+            '"' | '\'' => {
+                chars.next();
+                Token::Str(tokenize_string(&mut chars, c)?)
+            }
+            '=' | '!' | '<' | '>' | '&' | '|' => {
+                chars.next();
+                let next = chars.peek().map(|&(_, c)| c);
+                let (token, double) = match (c, next) {
+                    ('=', Some('=')) => (Token::Op(Op::Eq), true),
+                    ('!', Some('=')) => (Token::Op(Op::Ne), true),
+                    ('<', Some('=')) => (Token::Op(Op::Le), true),
+                    ('>', Some('=')) => (Token::Op(Op::Ge), true),
+                    ('&', Some('&')) => (Token::And, true),
+                    ('|', Some('|')) => (Token::Or, true),
+                    ('=', _) => (Token::Op(Op::Eq), false),
+                    ('!', _) => (Token::Not, false),
+                    ('<', _) => (Token::Op(Op::Lt), false),
+                    ('>', _) => (Token::Op(Op::Gt), false),
+                    _ => return Err(ExprError::UnexpectedToken(c.to_string())),
+                };
+                if double {
+                    chars.next();
+                }
+                token
+            }
+            _ => {
+                let word = tokenize_word(&mut chars, input, start);
+                match word {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "contains" => Token::Op(Op::Contains),
+                    _ => Token::Word(word.to_owned()),
+                }
+            }
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn tokenize_string(chars: &mut Peekable<CharIndices>, quote: char) -> Result<String, ExprError> {
+    let mut s = String::new();
+
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) => s.push(c),
+                None => break,
+            },
+            c if c == quote => return Ok(s),
+            c => s.push(c),
+        }
+    }
+
+    Err(ExprError::UnclosedString)
+}
+
+// a word ends at whitespace, parentheses or operators; brackets of a field
+// path are consumed as a whole, so that they may contain any of those
+fn tokenize_word<'a>(chars: &mut Peekable<CharIndices>, input: &'a str, start: usize) -> &'a str {
+    let mut end = input.len();
+    let mut in_bracket = false;
+
+    while let Some(&(i, c)) = chars.peek() {
+        if in_bracket {
+            in_bracket = c != ']';
+        } else if c == '[' {
+            in_bracket = true;
+        } else if c.is_whitespace() || "()=!<>&\"'".contains(c) {
+            end = i;
+            break;
+        } else if c == '|' && input[i + 1..].starts_with('|') {
+            // `a|b` are field alternatives, but `||` is an operator
+            end = i;
+            break;
+        }
+
+        chars.next();
+    }
+
+    &input[start..end]
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    i: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.i).cloned();
+        self.i += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.i) }
+
+    fn parse_or(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.parse_and()?;
+
+        while let Some(Token::Or) = self.peek() {
+            self.i += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.parse_unary()?;
+
+        while let Some(Token::And) = self.peek() {
+            self.i += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExprError> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    Some(token) => Err(ExprError::UnexpectedToken(token.to_string())),
+                    None => Err(ExprError::ClosingParen),
+                }
+            }
+            Some(Token::Word(word)) => self.parse_predicate(&word),
+            Some(token) => Err(ExprError::UnexpectedToken(token.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+
+    fn parse_predicate(&mut self, field: &str) -> Result<Expr, ExprError> {
+        let path = FieldPath::parse(field).map_err(|source| ExprError::Field {
+            source,
+            field: field.to_owned(),
+        })?;
+
+        match self.next() {
+            Some(Token::Word(w)) if w == "exists" => Ok(Expr::Exists(path)),
+            Some(Token::Word(w)) if w == "matches" => match self.next() {
+                Some(Token::Str(pattern)) => {
+                    let regex = Regex::new(&pattern)?;
+                    Ok(Expr::Matches(path, regex))
+                }
+                _ => Err(ExprError::ExpectedString("matches")),
+            },
+            Some(Token::Op(op)) => Ok(Expr::Compare(path, op, self.parse_literal()?)),
+            Some(token) => Err(ExprError::UnexpectedToken(token.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, ExprError> { Literal::parse(self.next()) }
+}
+
+impl Literal {
+    /// Reads the literal compared against, from the token after an operator.
+    pub(crate) fn parse(token: Option<Token>) -> Result<Literal, ExprError> {
+        match token {
+            Some(Token::Str(s)) => Ok(Literal::String(s)),
+            Some(Token::Word(w)) => {
+                // bare words are taken as strings, like `level == error`; only
+                // words starting with a digit are read as numbers, so `inf` or
+                // `nan` are words
+                let numeric = w
+                    .trim_start_matches('-')
+                    .starts_with(|c: char| c.is_ascii_digit());
+                Ok(match w.as_str() {
+                    "true" => Literal::Keyword("true"),
+                    "false" => Literal::Keyword("false"),
+                    "null" => Literal::Keyword("null"),
+                    _ if numeric => w.parse().map(Literal::Number).unwrap_or(Literal::String(w)),
+                    _ => Literal::String(w),
+                })
+            }
+            Some(token) => Err(ExprError::UnexpectedToken(token.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExprError {
+    #[error("Unexpected end of expression")]
+    UnexpectedEnd,
+    #[error("Unexpected token in expression '{0}'")]
+    UnexpectedToken(String),
+    #[error("Closing quote not found in expression")]
+    UnclosedString,
+    #[error("Closing parenthesis not found in expression")]
+    ClosingParen,
+    #[error("Expected a quoted string after '{0}'")]
+    ExpectedString(&'static str),
+    #[error("Invalid field in expression '{field}'")]
+    Field {
+        source: FormatError,
+        field: String,
+    },
+    #[error("Invalid regex in expression")]
+    Regex {
+        #[from]
+        source: regex::Error,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_json;
+
+    #[test]
+    fn matches() {
+        let json = parse_json(
+            r#"{"status": 503, "level": "ERROR", "msg": "upstream timed out", "cafe": "caf\u00e9", "limit": "nan", "retry": true, "data": {"user_id": 3175, "tags": ["a", "b"]}}"#,
+        )
+        .unwrap();
+
+        let test_cases = [
+            ("data.user_id == 3175", true),
+            ("data.user_id == 3176", false),
+            ("status >= 500 and data.user_id == 3175", true),
+            ("status < 500 or level == \"ERROR\"", true),
+            ("not (status < 500 || level == ERROR)", false),
+            ("!missing exists && retry == true", true),
+            ("missing != 3", true),
+            ("missing == null", true),
+            ("data.tags contains \"b\"", true),
+            ("msg contains 'timed'", true),
+            ("msg matches \"^up.*out$\"", true),
+            ("missing|status > 502", true),
+            ("data.tags[1] == b", true),
+            ("cafe == \"café\"", true),
+            ("limit == nan", true),
+            ("limit != -infinity", true),
+            ("status > -1", true),
+        ];
+
+        for (input, expected) in test_cases {
+            let expr = Expr::parse(input).unwrap();
+            assert_eq!(expr.matches(&json), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let test_cases = [
+            "status >=",
+            "(status > 3",
+            "status 3",
+            "msg matches foo",
+            "msg == \"foo",
+            "a == 1 b == 2",
+        ];
+
+        for input in test_cases {
+            assert!(Expr::parse(input).is_err(), "{input}");
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    format::FieldPath,
+    level::{Level, Levels},
+    time::parse_timestamp,
+    Json,
+};
+
+mod expr;
+
+pub use expr::Expr;
+pub(crate) use expr::{compare, tokenize, Literal, Op, Token};
+
+/// Decides which records are printed; records that don't match are dropped
+/// before formatting.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub level: Option<LevelFilter>,
+    /// expression given with `--where`
+    pub expr: Option<Expr>,
+    pub time: Option<TimeFilter>,
+}
+
+impl Filter {
+    pub fn matches(&self, json: &Json) -> bool {
+        if let Some(level) = &self.level {
+            if !level.matches(json) {
+                return false;
+            }
+        }
+
+        if let Some(time) = &self.time {
+            if !time.matches(json) {
+                return false;
+            }
+        }
+
+        if let Some(expr) = &self.expr {
+            if !expr.matches(json) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Matches records by the value of the level field.
+#[derive(Debug)]
+pub struct LevelFilter {
+    /// the level field, resolved from the `level` variable
+    pub field: FieldPath,
+    /// records below this level are dropped
+    pub min: Option<Level>,
+    /// if not empty, only records with one of these levels are kept
+    pub levels: Vec<Level>,
+    /// vocabulary of the level field
+    pub names: Levels,
+}
+
+impl LevelFilter {
+    fn matches(&self, json: &Json) -> bool {
+        // records without a recognizable level can't satisfy the filter
+        let Some(level) = self.names.parse(&self.field.resolve(json)) else {
+            return false;
+        };
+
+        self.min.is_none_or(|min| level >= min)
+            && (self.levels.is_empty() || self.levels.contains(&level))
+    }
+}
+
+/// Matches records by the time of the timestamp field.
+#[derive(Debug)]
+pub struct TimeFilter {
+    /// the timestamp field, resolved from the `timestamp` variable
+    pub field: FieldPath,
+    /// records before this time are dropped
+    pub since: Option<DateTime<Utc>>,
+    /// records after this time are dropped
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeFilter {
+    fn matches(&self, json: &Json) -> bool {
+        // records without a recognizable timestamp can't satisfy the filter
+        let Some(time) = parse_timestamp(&self.field.resolve(json)) else {
+            return false;
+        };
+        let time = time.to_utc();
+
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_json;
+
+    #[test]
+    fn levels() {
+        let records = [
+            r#"{"level": "debug"}"#,
+            r#"{"lvl": "WARN"}"#,
+            r#"{"level": 50}"#,
+            r#"{"level": "fatal"}"#,
+            r#"{"msg": "no level"}"#,
+            r#"{"level": "unknown"}"#,
+        ]
+        .map(|record| parse_json(record).unwrap());
+
+        let kept = |min: Option<Level>, levels: Vec<Level>| {
+            let filter = LevelFilter {
+                field: FieldPath::parse("{level|lvl}").unwrap(),
+                min,
+                levels,
+                names: Levels::default(),
+            };
+            records.each_ref().map(|json| filter.matches(json))
+        };
+
+        assert_eq!(kept(Some(Level::Warn), Vec::new()), [
+            false, true, true, true, false, false
+        ]);
+        assert_eq!(kept(None, vec![Level::Debug, Level::Error]), [
+            true, false, true, false, false, false
+        ]);
+        assert_eq!(
+            kept(Some(Level::Error), vec![Level::Debug, Level::Error]),
+            [false, false, true, false, false, false]
+        );
+    }
+}
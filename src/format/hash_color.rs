@@ -0,0 +1,143 @@
+use std::{env, sync::OnceLock};
+
+use owo_colors::{AnsiColors, DynColors, XtermColors};
+
+/// Colors supported by the terminal, which decides the palette of hashed
+/// colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Reads the depth from `COLORTERM` and `TERM`, once per run.
+    pub fn detect() -> ColorDepth {
+        static DEPTH: OnceLock<ColorDepth> = OnceLock::new();
+
+        *DEPTH.get_or_init(|| {
+            let colorterm = env::var("COLORTERM").unwrap_or_default();
+            let term = env::var("TERM").unwrap_or_default();
+
+            if colorterm == "truecolor" || colorterm == "24bit" {
+                ColorDepth::TrueColor
+            } else if term.contains("256color") {
+                ColorDepth::Ansi256
+            } else {
+                ColorDepth::Ansi16
+            }
+        })
+    }
+}
+
+// bright enough to be read on dark and light backgrounds; black, white and
+// grays are left out
+const ANSI_PALETTE: [AnsiColors; 12] = [
+    AnsiColors::Red,
+    AnsiColors::Green,
+    AnsiColors::Yellow,
+    AnsiColors::Blue,
+    AnsiColors::Magenta,
+    AnsiColors::Cyan,
+    AnsiColors::BrightRed,
+    AnsiColors::BrightGreen,
+    AnsiColors::BrightYellow,
+    AnsiColors::BrightBlue,
+    AnsiColors::BrightMagenta,
+    AnsiColors::BrightCyan,
+];
+
+// the 6x6x6 color cube of xterm, without the darkest levels and the grays
+const XTERM_PALETTE: [u8; 111] = xterm_palette();
+
+const fn xterm_palette() -> [u8; 111] {
+    let mut palette = [0; 111];
+    let mut i = 0;
+
+    let mut r = 1;
+    while r < 6 {
+        let mut g = 1;
+        while g < 6 {
+            let mut b = 1;
+            while b < 6 {
+                if !(r == g && g == b) && r + g + b >= 6 {
+                    palette[i] = 16 + 36 * r + 6 * g + b;
+                    i += 1;
+                }
+                b += 1;
+            }
+            g += 1;
+        }
+        r += 1;
+    }
+
+    assert!(i == palette.len());
+    palette
+}
+
+/// Color derived from the value, so equal values get the same color in every
+/// record and every run.
+pub fn hash_color(value: &str, depth: ColorDepth) -> DynColors {
+    let hash = fnv1a(value);
+
+    match depth {
+        ColorDepth::Ansi16 => DynColors::Ansi(ANSI_PALETTE[hash as usize % ANSI_PALETTE.len()]),
+        ColorDepth::Ansi256 => {
+            let color = XTERM_PALETTE[hash as usize % XTERM_PALETTE.len()];
+            DynColors::Xterm(XtermColors::from(color))
+        }
+        ColorDepth::TrueColor => {
+            let (r, g, b) = hsl_to_rgb((hash % 360) as f64, 0.65, 0.6);
+            DynColors::Rgb(r, g, b)
+        }
+    }
+}
+
+// stable across runs and platforms, unlike the std hasher
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (u8, u8, u8) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) = match h as u32 {
+        0..=59 => (c, x, 0.0),
+        60..=119 => (x, c, 0.0),
+        120..=179 => (0.0, c, x),
+        180..=239 => (0.0, x, c),
+        240..=299 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    let channel = |v: f64| ((v + m) * 255.0).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_colors() {
+        for depth in [
+            ColorDepth::Ansi16,
+            ColorDepth::Ansi256,
+            ColorDepth::TrueColor,
+        ] {
+            assert_eq!(hash_color("req-1", depth), hash_color("req-1", depth));
+            assert_ne!(hash_color("req-1", depth), hash_color("req-2", depth));
+        }
+
+        assert_eq!(
+            hash_color("abc", ColorDepth::Ansi16),
+            DynColors::Ansi(AnsiColors::Blue)
+        );
+        assert_eq!(hsl_to_rgb(0.0, 1.0, 0.5), (255, 0, 0));
+    }
+}
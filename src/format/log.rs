@@ -1,46 +1,156 @@
 use core::fmt;
+use std::borrow::Cow;
 
-use owo_colors::AnsiColors;
 pub use owo_colors::OwoColorize as Colorize;
 
+use super::hash_color::{self, ColorDepth};
+use super::value_filter::{self, Filtered};
 use super::*;
-use crate::json::PathToken;
-use crate::Json;
+use crate::filter::compare;
+use crate::json::{unescape, unescape_printable, PathToken};
+use crate::level::Levels;
+use crate::prefix::Prefix;
+use crate::{parse_json, Json};
 
 // used for displaying the formatted log to output
 pub struct FormattedLog<'a> {
     pub(super) formatter: &'a Formatter,
     pub(super) json: &'a Json<'a>,
+    pub(super) meta: Meta<'a>,
+}
+
+/// Values for the `@` fields, which come from where the record was read rather
+/// than from the record itself.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Meta<'a> {
+    pub file: Option<&'a str>,
+    pub prefix: Prefix<'a>,
+    pub line: Option<&'a str>,
 }
 
 impl fmt::Display for FormattedLog<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.write_fmt(f) }
 }
 
-impl FormattedLog<'_> {
+impl<'a> FormattedLog<'a> {
+    /// Sets the file name shown by `{@file}`.
+    pub fn with_file(mut self, file: Option<&'a str>) -> Self {
+        self.meta.file = file;
+        self
+    }
+
+    /// Sets the text of the line that's not a record, shown by `{@line}`.
+    pub fn with_line(mut self, line: &'a str) -> Self {
+        self.meta.line = Some(line);
+        self
+    }
+
+    /// Sets the text before the record shown by `{@prefix}`, `{@time}` and
+    /// `{@stream}`.
+    pub fn with_prefix(mut self, prefix: Prefix<'a>) -> Self {
+        self.meta.prefix = prefix;
+        self
+    }
+
     pub fn write_fmt(&self, f: &mut impl fmt::Write) -> Result<(), fmt::Error> {
         let Self {
-            formatter: Formatter { pieces, args },
+            formatter: Formatter { pieces, args, levels },
             json,
+            meta,
         } = self;
 
+        let ctx = Context {
+            args,
+            levels,
+            json,
+            meta,
+            item: None,
+        };
         let mut used_fields = SmallVec::new();
 
         let mut piece_i = 0;
         while piece_i < pieces.len() {
-            piece_i = write_piece(f, pieces, piece_i, args, json, false, &mut used_fields)?;
+            piece_i = write_piece(f, pieces, piece_i, &ctx, false, &mut used_fields)?;
         }
 
         Ok(())
     }
 }
 
+// values needed to resolve the args while writing a single log
+struct Context<'a, 'j> {
+    args: &'a [Arg],
+    levels: &'a Levels,
+    json: &'j Json<'j>,
+    meta: &'j Meta<'j>,
+    // item of the innermost `{#each}` being written
+    item: Option<&'j Item<'j>>,
+}
+
+// item of the array or object iterated by `{#each}`
+struct Item<'j> {
+    // item of the enclosing `{#each}`
+    parent: Option<&'j Item<'j>>,
+    name: Option<&'j str>,
+    key: Option<&'j str>,
+    value: &'j Json<'j>,
+    index: usize,
+    // index as text, for `{@index}`
+    index_str: String,
+    len: usize,
+}
+
+impl<'j> Context<'_, 'j> {
+    // get the value at the field path; paths starting with the name of an item
+    // are read from the item. Returns `true` with the value if it's from an item.
+    fn get(&self, names: &[FieldType]) -> (Cow<'j, Json<'j>>, bool) {
+        if let Some(FieldType::Name(first)) = names.first() {
+            let mut item = self.item;
+            while let Some(it) = item {
+                if it.name == Some(first.as_str()) {
+                    return (get_names(it.value, &names[1..]), true);
+                }
+                item = it.parent;
+            }
+        }
+
+        (get_names(self.json, names), false)
+    }
+
+    fn meta(&self, field: MetaField) -> Option<Cow<'j, Json<'j>>> {
+        let bool_json = |b: bool| Cow::Owned(Json::Value(if b { "true" } else { "false" }));
+
+        match field {
+            MetaField::File => self.meta.file.map(|file| Cow::Owned(Json::String(file))),
+            MetaField::Prefix => self
+                .meta
+                .prefix
+                .text
+                .map(|text| Cow::Owned(Json::String(text))),
+            MetaField::Time => self
+                .meta
+                .prefix
+                .time
+                .map(|time| Cow::Owned(Json::String(time))),
+            MetaField::Line => self.meta.line.map(|line| Cow::Owned(Json::String(line))),
+            MetaField::Stream => {
+                let stream = self.meta.prefix.stream;
+                stream.map(|stream| Cow::Owned(Json::String(stream)))
+            }
+            MetaField::Key => self.item?.key.map(|key| Cow::Owned(Json::String(key))),
+            MetaField::Value => Some(Cow::Borrowed(self.item?.value)),
+            MetaField::Index => Some(Cow::Owned(Json::Value(&self.item?.index_str))),
+            MetaField::First => Some(bool_json(self.item?.index == 0)),
+            MetaField::Last => self.item.map(|item| bool_json(item.index + 1 == item.len)),
+        }
+    }
+}
+
 fn write_piece<'a>(
     f: &mut impl fmt::Write,
     pieces: &'a Vec<Piece>,
     mut piece_i: usize,
-    args: &'a Vec<Arg>,
-    json: &'a Json<'a>,
+    ctx: &Context<'a, '_>,
     skip: bool,
     used_fields: &mut SmallVec<[&'a Field; 5]>,
 ) -> Result<usize, fmt::Error> {
@@ -59,32 +169,24 @@ fn write_piece<'a>(
         }
         Arg(i) => {
             if !skip {
-                write_arg(f, &args[*i], json, used_fields)?
+                write_arg(f, &ctx.args[*i], ctx, used_fields)?
             }
         }
-        CondStart(cond, i) => {
-            let cond_matched = !skip && test_cond(*cond, args, *i, json, used_fields);
-            let mut should_run = cond_matched;
-            let mut else_cond_matched = false;
+        CondStart(cond) => {
+            // whether any branch of the conditional has matched so far
+            let mut matched = !skip && test_cond(cond, ctx, used_fields);
+            let mut should_run = matched;
 
             piece_i += 1;
             while piece_i < pieces.len() {
-                if let Piece::ElseCond(cond, i) = pieces[piece_i] {
-                    if !skip && !cond_matched && !else_cond_matched {
-                        should_run = test_cond(cond, args, i, json, used_fields);
-                        else_cond_matched = true;
-                    } else {
-                        should_run = false;
-                    }
+                if let Piece::ElseCond(cond) = &pieces[piece_i] {
+                    should_run = !skip && !matched && test_cond(cond, ctx, used_fields);
+                    matched |= should_run;
 
                     piece_i += 1;
                 } else if let Piece::Else = pieces[piece_i] {
-                    if !skip && !should_run && !else_cond_matched {
-                        should_run = true;
-                        else_cond_matched = true;
-                    } else {
-                        should_run = false;
-                    }
+                    should_run = !skip && !matched;
+                    matched = true;
 
                     piece_i += 1;
                 }
@@ -93,9 +195,61 @@ fn write_piece<'a>(
                     break;
                 }
 
-                piece_i = write_piece(f, pieces, piece_i, args, json, !should_run, used_fields)?;
+                piece_i = write_piece(f, pieces, piece_i, ctx, !should_run, used_fields)?;
             }
         }
+        Each(i, name) => {
+            let value = if skip {
+                Cow::Borrowed(&Json::Null)
+            } else {
+                each_value(&ctx.args[*i].0, ctx, used_fields)
+            };
+
+            let items: Vec<_> = if let Some(arr) = value.as_array() {
+                arr.iter().map(|v| (None, v)).collect()
+            } else if let Some(obj) = value.as_object() {
+                obj.iter().map(|(k, v)| (Some(*k), v)).collect()
+            } else {
+                Vec::new()
+            };
+
+            let body = piece_i + 1;
+            let mut end = None;
+            for (index, (key, value)) in items.iter().enumerate() {
+                let item = Item {
+                    parent: ctx.item,
+                    name: name.as_deref(),
+                    key: *key,
+                    value,
+                    index,
+                    index_str: index.to_string(),
+                    len: items.len(),
+                };
+                let ctx = Context {
+                    args: ctx.args,
+                    levels: ctx.levels,
+                    json: ctx.json,
+                    meta: ctx.meta,
+                    item: Some(&item),
+                };
+
+                end = Some(write_block(f, pieces, body, &ctx, false, used_fields)?);
+            }
+
+            // without items, the block is still walked to find its end
+            let end = match end {
+                Some(end) => end,
+                None => write_block(f, pieces, body, ctx, true, used_fields)?,
+            };
+
+            // `{:else}` is written if there are no items
+            piece_i = if let Some(Piece::Else) = pieces.get(end) {
+                let skip = skip || !items.is_empty();
+                write_block(f, pieces, end + 1, ctx, skip, used_fields)?
+            } else {
+                end
+            };
+        }
         // Handled in the IfStart case above
         ElseCond(..) | Else | CondEnd => {}
     }
@@ -103,29 +257,122 @@ fn write_piece<'a>(
     Ok(piece_i + 1)
 }
 
+// write the pieces of a block until its end or `{:else}`, and return the index
+// where it stopped
+fn write_block<'a>(
+    f: &mut impl fmt::Write,
+    pieces: &'a Vec<Piece>,
+    mut piece_i: usize,
+    ctx: &Context<'a, '_>,
+    skip: bool,
+    used_fields: &mut SmallVec<[&'a Field; 5]>,
+) -> Result<usize, fmt::Error> {
+    while piece_i < pieces.len() && !matches!(pieces[piece_i], Piece::CondEnd | Piece::Else) {
+        piece_i = write_piece(f, pieces, piece_i, ctx, skip, used_fields)?;
+    }
+
+    Ok(piece_i)
+}
+
+// get the array or object iterated by `{#each}`
+fn each_value<'a, 'j>(
+    field_options: &'a FieldOptions,
+    ctx: &Context<'a, 'j>,
+    used_fields: &mut SmallVec<[&'a Field; 5]>,
+) -> Cow<'j, Json<'j>> {
+    for field in field_options {
+        let val = match field {
+            Field::Whole | Field::Rest => Cow::Borrowed(ctx.json),
+            Field::Meta(MetaField::Value) => {
+                Cow::Borrowed(ctx.item.map_or(&Json::Null, |item| item.value))
+            }
+            Field::Meta(_) | Field::Literal(..) => Cow::Borrowed(&Json::Null),
+            Field::Names(names) => {
+                let (val, from_item) = ctx.get(names);
+                if !val.is_null() && !from_item {
+                    used_fields.push(field);
+                }
+                val
+            }
+        };
+
+        if !val.is_null() {
+            return val;
+        }
+    }
+
+    Cow::Borrowed(&Json::Null)
+}
+
 fn test_cond<'a>(
-    cond: Cond,
-    args: &[Arg],
-    i: usize,
-    json: &'a Json<'a>,
+    cond: &Cond,
+    ctx: &Context<'a, '_>,
     used_fields: &SmallVec<[&'a Field; 5]>,
 ) -> bool {
-    if let Cond::IfConfig(b) = cond {
-        return b;
+    match cond {
+        Cond::IfConfig(b) => *b,
+        Cond::Key(i) => test_arg(true, *i, ctx, used_fields),
+        Cond::If(expr) => test_cond_expr(expr, ctx, used_fields),
     }
+}
 
-    let (field_options, _) = &args[i];
-    let mut val = &Json::Null;
-    for field in field_options {
-        val = json;
+fn test_cond_expr<'a>(
+    expr: &CondExpr,
+    ctx: &Context<'a, '_>,
+    used_fields: &SmallVec<[&'a Field; 5]>,
+) -> bool {
+    match expr {
+        CondExpr::Arg(i) => test_arg(false, *i, ctx, used_fields),
+        CondExpr::Compare(i, op, literal) => {
+            let (field_options, _) = &ctx.args[*i];
+            for field in field_options {
+                let val = match field {
+                    Field::Whole | Field::Rest => Cow::Borrowed(ctx.json),
+                    Field::Meta(field) => match ctx.meta(*field) {
+                        Some(value) => return compare(&value, *op, literal),
+                        None => continue,
+                    },
+                    Field::Literal(value, is_str) => {
+                        return compare(&super::literal(value, *is_str), *op, literal)
+                    }
+                    Field::Names(names) => ctx.get(names).0,
+                };
+
+                if !val.is_null() {
+                    return compare(&val, *op, literal);
+                }
+            }
+
+            compare(&Json::Null, *op, literal)
+        }
+        CondExpr::Not(expr) => !test_cond_expr(expr, ctx, used_fields),
+        CondExpr::And(a, b) => {
+            test_cond_expr(a, ctx, used_fields) && test_cond_expr(b, ctx, used_fields)
+        }
+        CondExpr::Or(a, b) => {
+            test_cond_expr(a, ctx, used_fields) || test_cond_expr(b, ctx, used_fields)
+        }
+    }
+}
 
+// test whether the arg exists if `key`, or else whether it's truthy
+fn test_arg<'a>(
+    key: bool,
+    i: usize,
+    ctx: &Context<'a, '_>,
+    used_fields: &SmallVec<[&'a Field; 5]>,
+) -> bool {
+    let json = ctx.json;
+    let (field_options, _) = &ctx.args[i];
+    let mut val = Cow::Borrowed(&Json::Null);
+    for field in field_options {
         match field {
-            Field::Whole => return test_cond2(cond, json),
+            Field::Whole => return test_cond2(key, json),
             Field::Rest => {
                 // optimization
                 // for `key` conditional, `rest` always exists
                 // since it's the base object
-                if cond == Cond::Key {
+                if key {
                     return true;
                 } else {
                     return with_excluded(used_fields, |excluded| {
@@ -133,17 +380,14 @@ fn test_cond<'a>(
                     });
                 }
             }
+            Field::Meta(field) => match ctx.meta(*field) {
+                Some(value) => return test_cond2(key, &value),
+                None => val = Cow::Borrowed(&Json::Null),
+            },
+            // literals always exist, like the field they stand in for
+            Field::Literal(value, is_str) => return test_cond2(key, &literal(value, *is_str)),
             Field::Names(names) => {
-                for arg in names {
-                    match arg {
-                        FieldType::Name(name) => {
-                            val = val.get(name);
-                        }
-                        FieldType::Index(index) => {
-                            val = val.get_i(*index);
-                        }
-                    }
-                }
+                val = ctx.get(names).0;
             }
         }
 
@@ -152,78 +396,172 @@ fn test_cond<'a>(
         }
     }
 
-    test_cond2(cond, val)
+    test_cond2(key, &val)
 }
 
-fn test_cond2(cond: Cond, json: &Json<'_>) -> bool {
-    if json.is_null() {
-        return false;
+// get the value at the field path
+//
+// the values matched by `[*]` and `..name` are collected into a new array;
+// `..name` with a single match is the value itself
+pub(super) fn get_names<'a>(json: &'a Json<'a>, names: &[FieldType]) -> Cow<'a, Json<'a>> {
+    let mut val = json;
+    for (i, arg) in names.iter().enumerate() {
+        match arg {
+            FieldType::Name(name) => {
+                val = val.get(name);
+            }
+            FieldType::Index(index) => {
+                val = val.get_i(*index);
+            }
+            FieldType::IndexFromEnd(n) => {
+                val = match val.as_array() {
+                    Some(arr) if *n <= arr.len() => &arr[arr.len() - n],
+                    _ => &Json::Null,
+                };
+            }
+            FieldType::Wildcard => {
+                let items: Vec<_> = if let Some(arr) = val.as_array() {
+                    arr.iter().collect()
+                } else if let Some(obj) = val.as_object() {
+                    obj.iter().map(|(_, v)| v).collect()
+                } else {
+                    return Cow::Borrowed(&Json::Null);
+                };
+
+                let values = items
+                    .into_iter()
+                    .map(|item| get_names(item, &names[i + 1..]))
+                    .filter(|v| !v.is_null())
+                    .map(Cow::into_owned)
+                    .collect();
+                return Cow::Owned(Json::Array(values));
+            }
+            FieldType::Descend(name) => {
+                let mut found = Vec::new();
+                descend(val, name, &mut found);
+
+                let mut values: Vec<_> = found
+                    .into_iter()
+                    .map(|item| get_names(item, &names[i + 1..]))
+                    .filter(|v| !v.is_null())
+                    .collect();
+                return match values.len() {
+                    0 => Cow::Borrowed(&Json::Null),
+                    1 => values.remove(0),
+                    _ => Cow::Owned(Json::Array(
+                        values.into_iter().map(Cow::into_owned).collect(),
+                    )),
+                };
+            }
+        }
     }
 
-    match cond {
-        Cond::Key => true,
-        Cond::If => {
-            if json.is_array() || json.is_object() {
-                !json.is_empty()
-            } else if let Some(json) = json.as_str() {
-                !json.is_empty()
-            } else if let Some(json) = json.as_value() {
-                !(json == "false"
-                    || json == "0"
-                    || json == "-0"
-                    || json == "0n"
-                    || json == "undefined"
-                    || json == "NaN")
-            } else {
-                unreachable!("all cases checked")
+    Cow::Borrowed(val)
+}
+
+// collect the values of the key at any depth of the json
+fn descend<'a>(json: &'a Json<'a>, name: &str, found: &mut Vec<&'a Json<'a>>) {
+    if let Some(obj) = json.as_object() {
+        for (key, value) in obj.iter() {
+            if *key == name && !value.is_null() {
+                found.push(value);
             }
+            descend(value, name, found);
         }
-        _ => unreachable!("checked above"),
+    } else if let Some(arr) = json.as_array() {
+        for value in arr {
+            descend(value, name, found);
+        }
+    }
+}
+
+fn test_cond2(key: bool, json: &Json<'_>) -> bool {
+    if json.is_null() {
+        return false;
+    }
+
+    if key {
+        true
+    } else if json.is_array() || json.is_object() {
+        !json.is_empty()
+    } else if let Some(json) = json.as_str() {
+        !json.is_empty()
+    } else if let Some(json) = json.as_value() {
+        !(json == "false"
+            || json == "0"
+            || json == "-0"
+            || json == "0n"
+            || json == "undefined"
+            || json == "NaN")
+    } else {
+        unreachable!("all cases checked")
     }
 }
 
 fn write_arg<'a>(
     f: &mut impl fmt::Write,
     (field_options, format): &'a (FieldOptions, Format),
-    json: &'a Json<'a>,
+    ctx: &Context<'a, '_>,
     used_fields: &mut SmallVec<[&'a Field; 5]>,
 ) -> fmt::Result {
-    let mut val = &Json::Null;
+    let json = ctx.json;
+    let mut val = Cow::Borrowed(&Json::Null);
 
     for field in field_options {
-        val = json;
-
         match field {
             Field::Whole => {
-                return write_arg2(f, format, json);
+                return write_arg2(f, format, json, ctx.levels);
             }
             Field::Rest => {
-                return write_rest(f, format, json, used_fields);
+                return write_rest(f, format, json, ctx.levels, used_fields);
+            }
+            Field::Meta(field) => match ctx.meta(*field) {
+                Some(value) => return write_arg2(f, format, &value, ctx.levels),
+                None => val = Cow::Borrowed(&Json::Null),
+            },
+            Field::Literal(value, is_str) => {
+                return write_arg2(f, format, &literal(value, *is_str), ctx.levels);
             }
             Field::Names(names) => {
-                for arg in names {
-                    match arg {
-                        FieldType::Name(name) => {
-                            val = val.get(name);
-                        }
-                        FieldType::Index(index) => {
-                            val = val.get_i(*index);
-                        }
-                    }
-                }
+                let from_item;
+                (val, from_item) = ctx.get(names);
 
                 if !val.is_null() {
-                    used_fields.push(field);
+                    // fields of items are not part of the rest of the record
+                    if !from_item {
+                        used_fields.push(field);
+                    }
                     break;
                 }
             }
         }
     }
 
-    write_arg2(f, format, val)
+    write_arg2(f, format, &val, ctx.levels)
+}
+
+fn write_arg2(
+    f: &mut impl fmt::Write,
+    format: &Format,
+    json: &Json<'_>,
+    levels: &Levels,
+) -> fmt::Result {
+    // the value is written first to measure its width
+    if let Some(width) = &format.width {
+        let mut value = String::new();
+        write_value(&mut value, format, json, levels)?;
+        return width.write(f, &value);
+    }
+
+    write_value(f, format, json, levels)
 }
 
-fn write_arg2(f: &mut impl fmt::Write, format: &Format, json: &Json<'_>) -> fmt::Result {
+fn write_value(
+    f: &mut impl fmt::Write,
+    format: &Format,
+    json: &Json<'_>,
+    levels: &Levels,
+) -> fmt::Result {
     let Format {
         style,
         compact,
@@ -231,42 +569,93 @@ fn write_arg2(f: &mut impl fmt::Write, format: &Format, json: &Json<'_>) -> fmt:
         is_level,
         indent,
         markup_styles: json_styles,
+        parse,
+        multiline,
+        raw,
+        hash_color,
+        level_words,
+        time,
+        filters,
+        ..
     } = format;
     let indent = *indent;
     let is_level = *is_level;
 
+    let nested;
+    let parsed;
+    let json = match parse.then(|| json.nested_json()).flatten() {
+        Some(text) => {
+            nested = text;
+            match parse_json(&nested) {
+                Ok(json) => {
+                    parsed = json;
+                    &parsed
+                }
+                Err(_) => json,
+            }
+        }
+        None => json,
+    };
+
+    let formatted;
+    let formatted_json;
+    let json = match time.as_ref().and_then(|time| time.format(json)) {
+        Some(time) => {
+            formatted = time;
+            formatted_json = Json::String(&formatted);
+            &formatted_json
+        }
+        None => json,
+    };
+
+    // strings are decoded, unless `raw`; control characters, like line breaks,
+    // are only decoded with `multiline`. Filtered text is already decoded
+    let mut decode: fn(&str) -> Cow<'_, str> = match (*raw, *multiline) {
+        (_, true) => unescape,
+        (false, false) => unescape_printable,
+        (true, false) => |text| Cow::Borrowed(text),
+    };
+    let filtered = value_filter::apply(filters, json, decode);
+    let filtered_json;
+    let json = match &filtered {
+        Filtered::Json(json) => *json,
+        Filtered::Text(text) => {
+            decode = |text| Cow::Borrowed(text);
+            filtered_json = Json::String(text);
+            &filtered_json
+        }
+    };
+
+    let style = &match (style, json.as_str().or_else(|| json.as_value())) {
+        (Some(style), Some(text)) if *hash_color => {
+            Some((*style).color(hash_color::hash_color(text, ColorDepth::detect())))
+        }
+        _ => *style,
+    };
+
     if indent > 0 {
         write!(f, "{:indent$}", "", indent = indent)?;
     }
 
-    if let Some(val) = json.as_str() {
+    if let Some(level) = is_level.then(|| levels.parse(json)).flatten() {
+        let label = levels.label(level, json);
         if let Some(style) = style {
-            if is_level {
-                match val {
-                    "TRACE" | "trace" => write!(
-                        f,
-                        "{}",
-                        val.style((*style).color(AnsiColors::Cyan).dimmed())
-                    )?,
-                    "DEBUG" | "debug" => {
-                        write!(f, "{}", val.style((*style).color(AnsiColors::Green)))?
-                    }
-                    "INFO" | "info" => {
-                        write!(f, " {}", val.style((*style).color(AnsiColors::Cyan)))?
-                    }
-                    "WARN" | "warn" => {
-                        write!(f, " {}", val.style((*style).color(AnsiColors::Yellow)))?
-                    }
-                    "ERROR" | "error" => {
-                        write!(f, "{}", val.style((*style).color(AnsiColors::Red)))?
-                    }
-                    _ => write!(f, "{}", val.style(*style))?,
+            write!(f, "{}", label.style(levels.style(level, *style)))?;
+        } else {
+            write!(f, "{}", label)?;
+        }
+    } else if let Some(val) = json.as_str() {
+        let val = decode(val);
+
+        if *multiline {
+            for (i, line) in val.lines().enumerate() {
+                if i > 0 {
+                    write!(f, "\n{:indent$}", "")?;
                 }
-            } else {
-                write!(f, "{}", val.style(*style))?;
+                write_text(f, line, style.as_ref(), *level_words, levels)?;
             }
         } else {
-            write!(f, "{}", val)?;
+            write_text(f, &val, style.as_ref(), *level_words, levels)?;
         }
     } else if let Some(val) = json.as_value() {
         if let Some(style) = style.as_ref() {
@@ -311,6 +700,61 @@ fn write_arg2(f: &mut impl fmt::Write, format: &Format, json: &Json<'_>) -> fmt:
     Ok(())
 }
 
+// write the text with the style; with `level_words`, the words naming a level,
+// like `ERROR` or `WARN`, are styled by their level
+fn write_text(
+    f: &mut impl fmt::Write,
+    text: &str,
+    style: Option<&Style>,
+    level_words: bool,
+    levels: &Levels,
+) -> fmt::Result {
+    let Some(style) = style else {
+        return f.write_str(text);
+    };
+    if !level_words {
+        return write!(f, "{}", text.style(*style));
+    }
+
+    let bytes = text.as_bytes();
+    // start of the text not written yet
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_alphabetic() {
+            i += 1;
+            continue;
+        }
+
+        let begin = i;
+        while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+            i += 1;
+        }
+
+        // only whole words in upper case, so prose like "information" is
+        // left alone
+        let word = &text[begin..i];
+        let is_upper = word.len() >= 3 && word.bytes().all(|b| b.is_ascii_uppercase());
+        let bounded = begin == 0 || !bytes[begin - 1].is_ascii_alphanumeric();
+        if let Some(level) = (is_upper && bounded)
+            .then(|| levels.parse(&Json::String(word)))
+            .flatten()
+        {
+            if start < begin {
+                write!(f, "{}", (&text[start..begin]).style(*style))?;
+            }
+            write!(f, "{}", word.style(levels.style(level, *style)))?;
+            start = i;
+        }
+    }
+
+    if start < text.len() {
+        write!(f, "{}", (&text[start..]).style(*style))?;
+    }
+
+    Ok(())
+}
+
 /// Builds the list of already-consumed field paths (as `PathToken` slices) from
 /// `used_fields`, so they can be skipped when rendering the rest object.
 ///
@@ -328,6 +772,9 @@ fn build_excluded<'a>(
                 .map(|t| match t {
                     FieldType::Name(name) => PathToken::Name(name.as_str()),
                     FieldType::Index(index) => PathToken::Index(*index),
+                    FieldType::IndexFromEnd(n) => PathToken::IndexFromEnd(*n),
+                    FieldType::Wildcard => PathToken::Any,
+                    FieldType::Descend(name) => PathToken::Descend(name.as_str()),
                 })
                 .collect();
             paths.push(tokens);
@@ -355,11 +802,12 @@ fn write_rest(
     f: &mut impl fmt::Write,
     format: &Format,
     json: &Json,
+    levels: &Levels,
     used_fields: &SmallVec<[&Field; 5]>,
 ) -> fmt::Result {
     // Scalars can't have "rest" fields removed; fall back to the normal path.
     if !(json.is_object() || json.is_array()) {
-        return write_arg2(f, format, json);
+        return write_arg2(f, format, json, levels);
     }
 
     let Format {
@@ -1,11 +1,25 @@
+use std::borrow::Cow;
+
 use smallvec::SmallVec;
 
-use crate::{json::MarkupStyles, Json};
+use crate::{
+    filter::{Literal, Op},
+    json::MarkupStyles,
+    level::Levels,
+    time::TimeFormat,
+    Json,
+};
 
+mod hash_color;
 mod log;
 pub mod parse;
+mod value_filter;
+mod width;
+
+use value_filter::ValueFilter;
+use width::Width;
 
-pub use log::FormattedLog;
+pub use log::{FormattedLog, Meta};
 pub use owo_colors::{OwoColorize as Colorize, Style};
 
 type Arg = (FieldOptions, Format);
@@ -17,12 +31,44 @@ enum Field {
     Names(FieldNames),
     Whole,
     Rest,
+    // special field prefixed with `@`, which is not part of the json record
+    Meta(MetaField),
+    // fallback value, like `"UNKNOWN"` or `8080`, and whether it's a string
+    Literal(String, bool),
+}
+
+// value of the literal fallback
+fn literal(value: &str, is_str: bool) -> Json<'_> {
+    if is_str {
+        Json::String(value)
+    } else {
+        Json::Value(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetaField {
+    // name of the file the record was read from
+    File,
+    // text before the record, and the time and stream read from it
+    Prefix,
+    Time,
+    Stream,
+    // text of a line that's not a record
+    Line,
+    // key, value and position of the item in the innermost `{#each}`
+    Key,
+    Value,
+    Index,
+    First,
+    Last,
 }
 
 #[derive(Debug, Clone)]
 pub struct Formatter {
     pieces: Vec<Piece>,
     args: Vec<Arg>,
+    levels: Levels,
 }
 
 impl Formatter {
@@ -36,43 +82,110 @@ impl Formatter {
 
         parse::crunch_input(&mut pieces, &mut args, input, no_color, compact)?;
 
-        Ok(Formatter { pieces, args })
+        Ok(Formatter {
+            pieces,
+            args,
+            levels: Levels::default(),
+        })
+    }
+
+    /// Sets the level names and styles used by the `level` modifier.
+    pub fn with_levels(mut self, levels: Levels) -> Self {
+        self.levels = levels;
+        self
     }
 
     pub fn as_log<'a>(&'a self, json: &'a Json<'a>) -> FormattedLog<'a> {
         FormattedLog {
             formatter: self,
             json,
+            meta: Meta::default(),
         }
     }
 }
 
+/// A field with its fallback options, like `level|lvl|severity`, for reading
+/// values from a record outside of a format string.
+#[derive(Debug, Default, Clone)]
+pub struct FieldPath(FieldOptions);
+
+impl FieldPath {
+    /// Parses the field from a format string holding a single field, like
+    /// `{level|lvl|severity}`. Modifiers after `:` are ignored.
+    pub fn parse(input: &str) -> Result<FieldPath, parse::FormatError> {
+        let mut field_options = FieldOptions::new();
+        parse::crunch_field_path(input, &mut field_options)?;
+
+        Ok(FieldPath(field_options))
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Returns the value of the first option that exists in the record.
+    pub fn resolve<'a>(&self, json: &'a Json<'a>) -> Cow<'a, Json<'a>> {
+        for field in &self.0 {
+            let val = match field {
+                Field::Whole | Field::Rest => Cow::Borrowed(json),
+                Field::Names(names) => log::get_names(json, names),
+                Field::Meta(_) | Field::Literal(..) => Cow::Borrowed(&Json::Null),
+            };
+
+            if !val.is_null() {
+                return val;
+            }
+        }
+
+        Cow::Borrowed(&Json::Null)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Piece {
     Literal(String),
     // arg index
     Arg(usize),
     Escaped(char),
-    CondStart(Cond, usize),
-    ElseCond(Cond, usize),
+    CondStart(Cond),
+    ElseCond(Cond),
     Else,
     CondEnd,
+    // arg index of the array or object, and the name of its items
+    Each(usize, Option<String>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Cond {
-    If,
-    Key,
+    If(CondExpr),
+    // arg index
+    Key(usize),
     IfConfig(bool),
 }
 
+/// Condition of `{#if}`, like `status >= 500 && !retry`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CondExpr {
+    // arg index; the arg is tested for truthiness
+    Arg(usize),
+    // arg index; the value of the arg is compared with the literal
+    Compare(usize, Op, Literal),
+    Not(Box<CondExpr>),
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum FieldType {
     Name(String),
     Index(usize),
+    // index from the end, like `[-1]` for the last item
+    IndexFromEnd(usize),
+    // `[*]`; every item of an array, or value of an object
+    Wildcard,
+    // `..name`; the key at any depth
+    Descend(String),
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Format {
     pub style: Option<Style>,
     pub compact: bool,
@@ -82,4 +195,206 @@ pub struct Format {
     // changes based on the level
     pub is_level: bool,
     pub markup_styles: MarkupStyles,
+    // parse string values holding JSON, like `"{\"user\":1}"`
+    pub parse: bool,
+    // write the escaped line breaks of strings as lines, each with the indent
+    pub multiline: bool,
+    // write strings with their escapes, like `caf\u00e9`, instead of decoded
+    pub raw: bool,
+    // color the value by its hash, so equal values share a color
+    pub hash_color: bool,
+    // color the level words of the text, like `ERROR`, by their level
+    pub level_words: bool,
+    // how to display the value if it's a timestamp
+    pub time: Option<TimeFormat>,
+    // transformations applied to the value before it's styled
+    pub filters: Vec<ValueFilter>,
+    pub width: Option<Width>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_json;
+
+    fn format(format: &str, input: &str) -> String {
+        let formatter = Formatter::new(format, true, false).unwrap();
+        let json = parse_json(input).unwrap();
+        formatter.as_log(&json).to_string()
+    }
+
+    #[test]
+    fn file() {
+        let formatter =
+            Formatter::new("{#key @file}{@file}: {/key}{msg|@file}", true, false).unwrap();
+        let json = parse_json(r#"{"msg": "hi"}"#).unwrap();
+        let formatted: Vec<_> = [Some("a.log"), Some("logs/b.log"), None]
+            .into_iter()
+            .map(|file| formatter.as_log(&json).with_file(file).to_string())
+            .collect();
+        assert_eq!(formatted, ["a.log: hi", "logs/b.log: hi", "hi"]);
+
+        // stdin has no file
+        let json = parse_json("{}").unwrap();
+        assert_eq!(formatter.as_log(&json).with_file(None).to_string(), "");
+        assert_eq!(
+            formatter.as_log(&json).with_file(Some("c.log")).to_string(),
+            "c.log: c.log"
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let status = "{#if status >= 500}5xx{:else if status >= 400}4xx{:else}ok{/if}";
+        assert_eq!(format(status, r#"{"status": 503}"#), "5xx");
+        assert_eq!(format(status, r#"{"status": 404}"#), "4xx");
+        assert_eq!(format(status, r#"{"status": 200}"#), "ok");
+
+        let retry =
+            r#"{#if level == "ERROR" && retry}retry{:else if !(retry || level == WARN)}ok{/if}"#;
+        assert_eq!(
+            format(retry, r#"{"level": "ERROR", "retry": true}"#),
+            "retry"
+        );
+        assert_eq!(format(retry, r#"{"level": "ERROR", "retry": false}"#), "ok");
+        assert_eq!(format(retry, r#"{"level": "WARN"}"#), "");
+
+        assert!(Formatter::new("{#if status >=}x{/if}", true, false).is_err());
+    }
+
+    #[test]
+    fn each() {
+        let input = r#"{"errors": [{"code": 1}, {"code": 2}], "tags": {"env": "prod", "zone": "eu"}, "empty": []}"#;
+
+        let errors = "{#each errors as e}{@index}:{e.code}{#if !@last}, {/if}{/each}";
+        assert_eq!(format(errors, input), "0:1, 1:2");
+
+        let tags = "{#each tags}{@key}={@value}{#if !@last} {/if}{/each}";
+        assert_eq!(format(tags, input), "env=prod zone=eu");
+
+        let empty = "{#each empty as e}{e}{:else}none{/each}";
+        assert_eq!(format(empty, input), "none");
+    }
+
+    #[test]
+    fn paths() {
+        let input = r#"{"items": [{"id": 1, "x": "a"}, {"id": 2, "x": "b"}], "resp": {"body": {"error": {"code": "E1"}}}, "http.status": 500}"#;
+
+        assert_eq!(format("{items[*].id:compact}", input), "[1,2]");
+        assert_eq!(format("{items[-1].x} {items[-3].x|\"-\"}", input), "b -");
+        assert_eq!(
+            format(r#"{["http.status"]} {..error.code}"#, input),
+            "500 E1"
+        );
+
+        let rest = "{items[*].id}{..error}{..:compact}";
+        assert!(format(rest, input)
+            .ends_with(r#"{"items":[{"x":"a"},{"x":"b"}],"resp":{"body":{}},"http.status":500}"#));
+    }
+
+    #[test]
+    fn literal_fallbacks() {
+        let fmt = r#"{level|lvl|"UNKNOWN"} {port|8080}{#key lvl|"-"} key{/key}"#;
+        assert_eq!(
+            format(fmt, r#"{"level": "info", "port": 80}"#),
+            "info 80 key"
+        );
+        assert_eq!(format(fmt, "{}"), "UNKNOWN 8080 key");
+
+        let quoted = r#"{sep|"a|b: c"}{#if port|8080 > 8000} high{/if}"#;
+        assert_eq!(format(quoted, "{}"), "a|b: c high");
+
+        // numbers are keys unless they are the last fallback
+        let keys = r#"{200} {0|x|"-"}"#;
+        assert_eq!(format(keys, r#"{"200": "ok", "0": "zero"}"#), "ok zero");
+        assert_eq!(format(keys, "{}"), " -");
+    }
+
+    #[test]
+    fn multiline() {
+        let input = r#"{"stack": "Error: boom\n\tat f (a.js:1)\r\n\tat \"g\""}"#;
+        assert_eq!(
+            format("{stack:multiline,indent=2}", input),
+            "  Error: boom\n  \tat f (a.js:1)\n  \tat \"g\""
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        let input = r#"{"msg": "caf\u00e9 \"hi\" \ud83d\ude00 C:\\tmp"}"#;
+        assert_eq!(format("{msg}", input), r#"café "hi" 😀 C:\tmp"#);
+        assert_eq!(
+            format("{msg:raw}", input),
+            r#"caf\u00e9 \"hi\" \ud83d\ude00 C:\\tmp"#
+        );
+        assert_eq!(format("{msg|>upper}", input), r#"CAFÉ "HI" 😀 C:\TMP"#);
+
+        // control characters stay escaped, unless `multiline`
+        let input = r#"{"msg": "a\u001b[2J\r\nb"}"#;
+        assert_eq!(format("{msg}", input), r"a\u001b[2J\r\nb");
+        assert_eq!(format("{msg:multiline}", input), "a\u{1b}[2J\nb");
+    }
+
+    #[test]
+    fn level_words() {
+        let formatter = Formatter::new("{@line:level_words}", false, false).unwrap();
+        let line = "ERROR in 5WARN: INFO, ERRORS, NAN, INF";
+        let styled = formatter.as_log(&Json::Null).with_line(line).to_string();
+
+        let red = Style::new().red();
+        let cyan = Style::new().cyan();
+        assert_eq!(
+            styled,
+            format!(
+                "{} in 5WARN: {}, ERRORS, NAN, {}",
+                "ERROR".style(red),
+                "INFO".style(cyan),
+                "INF".style(cyan)
+            )
+        );
+    }
+
+    #[test]
+    fn value_filters() {
+        let input = r#"{"msg": "  Hello, World  ", "port": 80, "tags": ["a", "b"]}"#;
+
+        assert_eq!(format("{msg|>trim|>upper}", input), "HELLO, WORLD");
+        assert_eq!(format("{msg|>trim|>truncate(5)}", input), "Hell…");
+        assert_eq!(
+            format("[{port|>pad_left(4)}][{port|>pad_right(4)}]", input),
+            "[  80][80  ]"
+        );
+        assert_eq!(
+            format(r#"{msg|>trim|>replace(", ", ":")}"#, input),
+            "Hello:World"
+        );
+        assert_eq!(
+            format(r#"{host|>default("localhost")}:{port}"#, input),
+            "localhost:80"
+        );
+        assert_eq!(format("{tags|>len} {msg|>len}", input), "2 16");
+        assert_eq!(format(r#"{x|"a|>b"|>upper}"#, input), "A|>B");
+
+        assert!(Formatter::new("{msg|>truncate(x)}", true, false).is_err());
+        assert!(Formatter::new("{msg|>unknown}", true, false).is_err());
+    }
+
+    #[test]
+    fn widths() {
+        let input = r#"{"level": "info", "target": "app::server::http"}"#;
+
+        assert_eq!(
+            format("[{level:<6}][{level:>6}][{level:^7}]", input),
+            "[info  ][  info][ info  ]"
+        );
+        assert_eq!(format("[{target:<8}]", input), "[app::server::http]");
+        assert_eq!(
+            format("[{target:<8,overflow=truncate}]", input),
+            "[app::se…]"
+        );
+
+        let styled = Formatter::new("{level:red,>6}", false, false).unwrap();
+        let json = parse_json(input).unwrap();
+        assert_eq!(styled.as_log(&json).to_string(), "  \x1b[31minfo\x1b[0m");
+    }
 }
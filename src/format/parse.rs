@@ -1,12 +1,18 @@
-use std::num::ParseIntError;
+use std::{fmt, iter::Peekable, num::ParseIntError, vec};
 
 use owo_colors::Style;
 use smallvec::SmallVec;
 
-use super::{Arg, Cond, Field, FieldOptions, FieldType, Format, Piece};
+use super::{
+    value_filter::{find_unquoted, parse_filters, split_unquoted},
+    width::Width,
+    Arg, Cond, CondExpr, Field, FieldOptions, FieldType, Format, MetaField, Piece,
+};
 use crate::{
     colors::{parse_color, ParseColorError},
+    filter::{tokenize, Literal, Token},
     json::MarkupStyles,
+    time::{Relative, TimeFormat, Zone},
 };
 
 pub(super) fn crunch_input(
@@ -112,10 +118,12 @@ fn crunch_cond(
     no_color: bool,
     compact: bool,
 ) -> Result<(), FormatError> {
-    let (content, cond) = if let Some(content) = content.strip_prefix("if ") {
-        (content, Cond::If)
+    let cond = if let Some(content) = content.strip_prefix("each ") {
+        return crunch_each(pieces, args, content, no_color, compact);
+    } else if let Some(content) = content.strip_prefix("if ") {
+        Cond::If(crunch_cond_expr(args, content, no_color, compact)?)
     } else if let Some(content) = content.strip_prefix("key ") {
-        (content, Cond::Key)
+        Cond::Key(crunch_cond_key(args, content, no_color, compact)?)
     } else if let Some(content) = content.strip_prefix("config ") {
         let b = if content == "compact" {
             compact
@@ -126,20 +134,14 @@ fn crunch_cond(
                 config: content.to_owned(),
             });
         };
-        pieces.push(Piece::CondStart(Cond::IfConfig(b), 0));
-
-        return Ok(());
+        Cond::IfConfig(b)
     } else {
         return Err(FormatError::UnsupportedConditional {
             cond: format!("#{content}"),
         });
     };
 
-    let mut field_options = FieldOptions::new();
-    crunch_field_options(content, &mut field_options)?;
-
-    args.push((field_options, parse_format(None, no_color, compact)?));
-    pieces.push(Piece::CondStart(cond, args.len() - 1));
+    pieces.push(Piece::CondStart(cond));
 
     Ok(())
 }
@@ -152,10 +154,10 @@ fn crunch_cond_else(
     no_color: bool,
     compact: bool,
 ) -> Result<(), FormatError> {
-    let (content, cond) = if let Some(content) = content.strip_prefix("else if ") {
-        (content, Cond::If)
+    let cond = if let Some(content) = content.strip_prefix("else if ") {
+        Cond::If(crunch_cond_expr(args, content, no_color, compact)?)
     } else if let Some(content) = content.strip_prefix("else key ") {
-        (content, Cond::Key)
+        Cond::Key(crunch_cond_key(args, content, no_color, compact)?)
     } else if content == "else" {
         pieces.push(Piece::Else);
         return Ok(());
@@ -165,13 +167,149 @@ fn crunch_cond_else(
         });
     };
 
+    pieces.push(Piece::ElseCond(cond));
+
+    Ok(())
+}
+
+// parse `{#each items as item}`, where `as item` is optional
+fn crunch_each(
+    pieces: &mut Vec<Piece>,
+    args: &mut Vec<Arg>,
+    content: &str,
+    no_color: bool,
+    compact: bool,
+) -> Result<(), FormatError> {
+    let (content, alias) = match content.split_once(" as ") {
+        Some((content, alias)) => {
+            let alias = alias.trim();
+            if alias.is_empty() || alias.contains(['.', '[', '|', '@']) {
+                return Err(FormatError::InvalidEachName(alias.to_owned()));
+            }
+            (content, Some(alias.to_owned()))
+        }
+        None => (content, None),
+    };
+
+    let i = crunch_cond_key(args, content.trim(), no_color, compact)?;
+    pieces.push(Piece::Each(i, alias));
+
+    Ok(())
+}
+
+// push the fields of `{#key}` as an arg, and return its index
+fn crunch_cond_key(
+    args: &mut Vec<Arg>,
+    content: &str,
+    no_color: bool,
+    compact: bool,
+) -> Result<usize, FormatError> {
     let mut field_options = FieldOptions::new();
     crunch_field_options(content, &mut field_options)?;
 
     args.push((field_options, parse_format(None, no_color, compact)?));
-    pieces.push(Piece::ElseCond(cond, args.len() - 1));
 
-    Ok(())
+    Ok(args.len() - 1)
+}
+
+// parse the condition of `{#if}`, like `status >= 500 && !retry`; each field in
+// the condition is pushed as an arg
+fn crunch_cond_expr(
+    args: &mut Vec<Arg>,
+    content: &str,
+    no_color: bool,
+    compact: bool,
+) -> Result<CondExpr, FormatError> {
+    let tokens = tokenize(content).map_err(|e| invalid_cond(content, e))?;
+    // fields of empty variables may leave no condition, which is never true
+    if tokens.is_empty() {
+        return Ok(CondExpr::Arg(crunch_cond_key(args, "", no_color, compact)?));
+    }
+
+    let mut parser = CondParser {
+        content,
+        tokens: tokens.into_iter().peekable(),
+        args,
+        no_color,
+        compact,
+    };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.tokens.next() {
+        return Err(parser.unexpected(Some(token)));
+    }
+
+    Ok(expr)
+}
+
+fn invalid_cond(cond: &str, reason: impl fmt::Display) -> FormatError {
+    FormatError::InvalidCondition {
+        cond: cond.to_owned(),
+        reason: reason.to_string(),
+    }
+}
+
+struct CondParser<'a> {
+    content: &'a str,
+    tokens: Peekable<vec::IntoIter<Token>>,
+    args: &'a mut Vec<Arg>,
+    no_color: bool,
+    compact: bool,
+}
+
+impl CondParser<'_> {
+    fn parse_or(&mut self) -> Result<CondExpr, FormatError> {
+        let mut expr = self.parse_and()?;
+
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = CondExpr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<CondExpr, FormatError> {
+        let mut expr = self.parse_unary()?;
+
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = CondExpr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<CondExpr, FormatError> {
+        match self.tokens.next() {
+            Some(Token::Not) => Ok(CondExpr::Not(Box::new(self.parse_unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.tokens.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    token => Err(self.unexpected(token)),
+                }
+            }
+            Some(Token::Word(field)) => {
+                let i = crunch_cond_key(self.args, &field, self.no_color, self.compact)?;
+
+                if let Some(Token::Op(op)) = self.tokens.peek() {
+                    let op = *op;
+                    self.tokens.next();
+                    let literal = Literal::parse(self.tokens.next())
+                        .map_err(|e| invalid_cond(self.content, e))?;
+                    Ok(CondExpr::Compare(i, op, literal))
+                } else {
+                    Ok(CondExpr::Arg(i))
+                }
+            }
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    fn unexpected(&self, token: Option<Token>) -> FormatError {
+        match token {
+            Some(token) => invalid_cond(self.content, format_args!("unexpected '{token}'")),
+            None => invalid_cond(self.content, "unexpected end"),
+        }
+    }
 }
 
 #[inline]
@@ -192,11 +330,23 @@ fn crunch_arg(
     let content = content.trim();
 
     // param is a field
-    let (name_part, format) = match content.split_once(':') {
-        Some((name, styles)) => (name, parse_format(Some(styles), no_color, compact)?),
+    let (name_part, mut format) = match find_unquoted(content, ":") {
+        Some(i) => (
+            &content[..i],
+            parse_format(Some(&content[i + 1..]), no_color, compact)?,
+        ),
         None => (content, parse_format(None, no_color, compact)?),
     };
 
+    // filters of the value follow the field names, like `{message|>upper}`
+    let name_part = match find_unquoted(name_part, "|>") {
+        Some(i) => {
+            format.filters = parse_filters(&name_part[i + 2..])?;
+            &name_part[..i]
+        }
+        None => name_part,
+    };
+
     let mut fields = FieldOptions::new();
     crunch_field_options(name_part, &mut fields)?;
 
@@ -213,9 +363,17 @@ fn crunch_field_options(
     if content.is_empty() {
         return Ok(());
     } else {
-        for field in content.split('|') {
-            if !field.is_empty() {
-                field_options.push(parse_field(field)?);
+        let fields: Vec<_> = split_unquoted(content, "|")
+            .into_iter()
+            .filter(|field| !field.is_empty())
+            .collect();
+
+        for (i, field) in fields.iter().enumerate() {
+            // literals are only the last fallback, so `{200}` is still a key
+            let is_fallback = i > 0 && i == fields.len() - 1;
+            match is_fallback.then(|| parse_literal(field)).flatten() {
+                Some(literal) => field_options.push(literal),
+                None => field_options.push(parse_field(field)?),
             }
         }
     }
@@ -223,39 +381,116 @@ fn crunch_field_options(
     Ok(())
 }
 
+pub(super) fn crunch_field_path(
+    input: &str,
+    field_options: &mut FieldOptions,
+) -> Result<(), FormatError> {
+    let input = input.trim();
+    let content = input
+        .strip_prefix('{')
+        .and_then(|e| e.strip_suffix('}'))
+        .unwrap_or(input);
+    let name_part = find_unquoted(content, ":").map_or(content, |i| &content[..i]);
+    let name_part = find_unquoted(name_part, "|>").map_or(name_part, |i| &name_part[..i]);
+
+    crunch_field_options(name_part.trim(), field_options)
+}
+
 // parse a field str into list of possible names and/or index
 // e.g. "field1.field2[0].field3" -> [Name("field1"), Name("field2"), Index(0),
 // Name("field3")]
+//
+// keys containing '.' can be quoted in brackets, e.g. `["log.level"]`; `[*]`
+// maps over all items, `[-1]` indexes from the end, and `..name` finds the key
+// at any depth
 fn parse_field(name: &str) -> Result<Field, FormatError> {
     // field is whole or rest
     if name == "." {
         return Ok(Field::Whole);
     } else if name == ".." {
         return Ok(Field::Rest);
+    } else if let Some(meta) = name.strip_prefix('@').and_then(parse_meta_field) {
+        return Ok(Field::Meta(meta));
     }
 
     let mut args = SmallVec::new();
-
-    for part in name.split('.') {
-        if let Some((name, index)) = part.split_once('[') {
-            args.push(FieldType::Name(name.to_owned()));
-            if index.ends_with(']') {
-                let index = index
-                    .trim_end_matches(']')
-                    .parse()
-                    .toss_parse_index_with(|| index.to_owned())?;
-                args.push(FieldType::Index(index));
+    let mut rest = name;
+
+    while !rest.is_empty() {
+        if let Some(bracket) = rest.strip_prefix('[') {
+            if let Some(quoted) = bracket.strip_prefix('"') {
+                let end = quoted.find("\"]").ok_or(FormatError::IndexBracket)?;
+                args.push(FieldType::Name(quoted[..end].to_owned()));
+                rest = &quoted[end + 2..];
             } else {
-                return Err(FormatError::IndexBracket);
+                let end = bracket.find(']').ok_or(FormatError::IndexBracket)?;
+                let index = &bracket[..end];
+                let arg = if index == "*" {
+                    FieldType::Wildcard
+                } else if let Some(from_end) = index.strip_prefix('-') {
+                    let from_end = from_end
+                        .parse()
+                        .toss_parse_index_with(|| index.to_owned())?;
+                    FieldType::IndexFromEnd(from_end)
+                } else {
+                    let index = index.parse().toss_parse_index_with(|| index.to_owned())?;
+                    FieldType::Index(index)
+                };
+                args.push(arg);
+                rest = &bracket[end + 1..];
             }
+        } else if let Some(descend) = rest.strip_prefix("..") {
+            let end = descend.find(['.', '[']).unwrap_or(descend.len());
+            args.push(FieldType::Descend(descend[..end].to_owned()));
+            rest = &descend[end..];
         } else {
-            args.push(FieldType::Name(part.to_owned()));
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            args.push(FieldType::Name(rest[..end].to_owned()));
+            rest = &rest[end..];
+        }
+
+        // `..` is kept for the recursive descent
+        if !rest.starts_with("..") {
+            rest = rest.strip_prefix('.').unwrap_or(rest);
         }
     }
 
     Ok(Field::Names(args))
 }
 
+// parse a literal fallback, like `"UNKNOWN"`, `'-'` or `8080`
+fn parse_literal(name: &str) -> Option<Field> {
+    for quote in ['"', '\''] {
+        if let Some(value) = name.strip_prefix(quote) {
+            let value = value.strip_suffix(quote)?;
+            return Some(Field::Literal(value.to_owned(), true));
+        }
+    }
+
+    let is_number =
+        name.starts_with(|c: char| c.is_ascii_digit() || c == '-') && name.parse::<f64>().is_ok();
+    is_number.then(|| Field::Literal(name.to_owned(), false))
+}
+
+// parse a field name prefixed with '@', e.g. "@file" -> MetaField::File
+//
+// other names are regular keys of the record, like `@timestamp`
+fn parse_meta_field(name: &str) -> Option<MetaField> {
+    match name {
+        "file" => Some(MetaField::File),
+        "prefix" => Some(MetaField::Prefix),
+        "time" => Some(MetaField::Time),
+        "stream" => Some(MetaField::Stream),
+        "line" => Some(MetaField::Line),
+        "key" => Some(MetaField::Key),
+        "value" => Some(MetaField::Value),
+        "index" => Some(MetaField::Index),
+        "first" => Some(MetaField::First),
+        "last" => Some(MetaField::Last),
+        _ => None,
+    }
+}
+
 pub fn parse_format(
     input: Option<&str>,
     no_color: bool,
@@ -265,7 +500,15 @@ pub fn parse_format(
     let mut is_json = false;
     let mut indent = 0;
     let mut is_level = false;
+    let mut parse = false;
+    let mut multiline = false;
+    let mut raw = false;
+    let mut hash_color = false;
+    let mut level_words = false;
     let mut markup_styles = MarkupStyles::default();
+    let mut time: Option<TimeFormat> = None;
+    let mut width: Option<Width> = None;
+    let mut truncate = false;
 
     let Some(input) = input else {
         return Ok(Format {
@@ -275,14 +518,36 @@ pub fn parse_format(
             indent,
             is_level,
             markup_styles,
+            parse,
+            multiline,
+            raw,
+            hash_color,
+            level_words,
+            time,
+            filters: Vec::new(),
+            width,
         });
     };
 
-    for part in input.split(',') {
+    for part in split_modifiers(input) {
         if part.is_empty() {
             continue;
         }
 
+        // `time(..)` may contain any character, so it's checked first
+        if let Some(format) = part.strip_prefix("time(").and_then(|e| e.strip_suffix(')')) {
+            if !TimeFormat::is_valid_format(format) {
+                return Err(FormatError::InvalidTimeFormat(format.to_owned()));
+            }
+            time.get_or_insert_with(TimeFormat::default).format = Some(format.to_owned());
+            continue;
+        }
+
+        if let Some(w) = Width::parse(part) {
+            width = Some(w);
+            continue;
+        }
+
         let (name, value) = if let Some((name, value)) = part.split_once('=') {
             (name, value)
         } else {
@@ -301,6 +566,26 @@ pub fn parse_format(
                     is_json = true;
                     continue;
                 }
+                "parse" => {
+                    parse = true;
+                    continue;
+                }
+                "multiline" => {
+                    multiline = true;
+                    continue;
+                }
+                "raw" => {
+                    raw = true;
+                    continue;
+                }
+                "hash_color" => {
+                    hash_color = true;
+                    continue;
+                }
+                "level_words" => {
+                    level_words = true;
+                    continue;
+                }
                 "dimmed" => {
                     if let Some(s) = style.take() {
                         style = Some(s.dimmed());
@@ -313,6 +598,24 @@ pub fn parse_format(
                     }
                     continue;
                 }
+                // modifiers for timestamp fields
+                "local" => {
+                    time.get_or_insert_with(TimeFormat::default).zone = Some(Zone::Local);
+                    continue;
+                }
+                "utc" => {
+                    time.get_or_insert_with(TimeFormat::default).zone = Some(Zone::Utc);
+                    continue;
+                }
+                "since_start" => {
+                    time.get_or_insert_with(TimeFormat::default).relative =
+                        Some(Relative::SinceStart);
+                    continue;
+                }
+                "delta" => {
+                    time.get_or_insert_with(TimeFormat::default).relative = Some(Relative::Delta);
+                    continue;
+                }
                 _ => {}
             }
 
@@ -354,10 +657,19 @@ pub fn parse_format(
                 let color = parse_color(value).toss_parse_color()?;
                 markup_styles.syntax = markup_styles.syntax.color(color);
             }
+            "overflow" => match value {
+                "truncate" => truncate = true,
+                "expand" => truncate = false,
+                _ => return Err(FormatError::InvalidModifier(part.to_owned())),
+            },
             _ => return Err(FormatError::InvalidModifier(name.to_owned())),
         }
     }
 
+    if let Some(width) = &mut width {
+        width.truncate = truncate;
+    }
+
     Ok(Format {
         style,
         compact,
@@ -365,6 +677,29 @@ pub fn parse_format(
         indent,
         is_level,
         markup_styles,
+        parse,
+        multiline,
+        raw,
+        hash_color,
+        level_words,
+        time,
+        filters: Vec::new(),
+        width,
+    })
+}
+
+// split modifiers by ',', except for the ones inside parentheses like
+// `time(%b %d, %H:%M)`
+fn split_modifiers(input: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0;
+
+    input.split(move |c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        c == ',' && depth == 0
     })
 }
 
@@ -379,8 +714,12 @@ pub enum FormatError {
     },
     #[error("Invalid indent value in format string '{0}'")]
     ParseIndent(String),
+    #[error("Invalid time format in format string '{0}'")]
+    InvalidTimeFormat(String),
     #[error("Invalid modifier in format string '{0}'")]
     InvalidModifier(String),
+    #[error("Invalid filter in format string '{0}'")]
+    InvalidFilter(String),
     #[error("Unknown character escape in format string '\\{0}'")]
     UnknownCharEscape(char),
     #[error("Closing brace not found in format string")]
@@ -392,6 +731,13 @@ pub enum FormatError {
         source: ParseIntError,
         value: String,
     },
+    #[error("Invalid condition in format string '{cond}': {reason}")]
+    InvalidCondition {
+        cond: String,
+        reason: String,
+    },
+    #[error("Invalid name of items in format string '{0}'")]
+    InvalidEachName(String),
     #[error("Unsupported conitional '{cond}'")]
     UnsupportedConditional { cond: String },
     #[error("Unsupported config value in formatter '{config}'")]
@@ -0,0 +1,172 @@
+use std::borrow::Cow;
+
+use super::parse::FormatError;
+use crate::Json;
+
+/// Transformation of a field value, chained after the field names with `|>`,
+/// like `{message|>truncate(80)|>upper}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueFilter {
+    Upper,
+    Lower,
+    Trim,
+    /// shorten to the number of characters, ending with `…` if cut
+    Truncate(usize),
+    PadLeft(usize),
+    PadRight(usize),
+    Replace(String, String),
+    /// value to display if the field is missing or null
+    Default(String),
+    /// number of characters of a string, or items of an array or object
+    Len,
+}
+
+/// Value after the filters are applied.
+pub enum Filtered<'a> {
+    Json(&'a Json<'a>),
+    Text(String),
+}
+
+/// Applies the filters in order; objects and arrays are only changed by
+/// `len`, and null values only by `default`. Strings are filtered with their
+/// escapes decoded by `decode`.
+pub fn apply<'a>(
+    filters: &[ValueFilter],
+    json: &'a Json<'a>,
+    decode: fn(&str) -> Cow<'_, str>,
+) -> Filtered<'a> {
+    let mut value = Filtered::Json(json);
+
+    for filter in filters {
+        let filtered = match &value {
+            Filtered::Text(text) => filter.apply(text),
+            Filtered::Json(json) => match (json.as_str(), json.as_value()) {
+                (Some(text), _) => filter.apply(&decode(text)),
+                (None, Some(text)) => filter.apply(text),
+                (None, None) => {
+                    let len = json
+                        .as_array()
+                        .map(Vec::len)
+                        .or_else(|| json.as_object().map(|object| object.0.len()));
+
+                    match (filter, len) {
+                        (ValueFilter::Default(default), _) if json.is_null() => default.clone(),
+                        (ValueFilter::Len, Some(len)) => len.to_string(),
+                        _ => continue,
+                    }
+                }
+            },
+        };
+
+        value = Filtered::Text(filtered);
+    }
+
+    value
+}
+
+impl ValueFilter {
+    fn apply(&self, text: &str) -> String {
+        match self {
+            ValueFilter::Upper => text.to_uppercase(),
+            ValueFilter::Lower => text.to_lowercase(),
+            ValueFilter::Trim => text.trim().to_owned(),
+            ValueFilter::Truncate(n) => {
+                if text.chars().count() <= *n {
+                    text.to_owned()
+                } else if *n == 0 {
+                    String::new()
+                } else {
+                    let mut truncated: String = text.chars().take(n - 1).collect();
+                    truncated.push('…');
+                    truncated
+                }
+            }
+            ValueFilter::PadLeft(n) => format!("{text:>n$}"),
+            ValueFilter::PadRight(n) => format!("{text:<n$}"),
+            ValueFilter::Replace(from, to) => text.replace(from.as_str(), to),
+            ValueFilter::Default(_) => text.to_owned(),
+            ValueFilter::Len => text.chars().count().to_string(),
+        }
+    }
+}
+
+/// Parses the filter chain following the first `|>` of a field, like
+/// `truncate(80)|>upper`.
+pub fn parse_filters(input: &str) -> Result<Vec<ValueFilter>, FormatError> {
+    split_unquoted(input, "|>")
+        .into_iter()
+        .map(|filter| parse_filter(filter.trim()))
+        .collect()
+}
+
+fn parse_filter(input: &str) -> Result<ValueFilter, FormatError> {
+    let err = || FormatError::InvalidFilter(input.to_owned());
+
+    let (name, args) = match input.split_once('(') {
+        Some((name, args)) => {
+            let args = args.strip_suffix(')').ok_or_else(err)?;
+            let args: Vec<_> = split_unquoted(args, ",")
+                .into_iter()
+                .map(|arg| unquote(arg.trim()))
+                .collect();
+            (name.trim(), args)
+        }
+        None => (input, Vec::new()),
+    };
+
+    let number = |arg: &str| arg.parse::<usize>().map_err(|_| err());
+
+    let filter = match (name, args.as_slice()) {
+        ("upper", []) => ValueFilter::Upper,
+        ("lower", []) => ValueFilter::Lower,
+        ("trim", []) => ValueFilter::Trim,
+        ("len", []) => ValueFilter::Len,
+        ("truncate", [n]) => ValueFilter::Truncate(number(n)?),
+        ("pad_left", [n]) => ValueFilter::PadLeft(number(n)?),
+        ("pad_right", [n]) => ValueFilter::PadRight(number(n)?),
+        ("replace", [from, to]) => ValueFilter::Replace(from.to_string(), to.to_string()),
+        ("default", [default]) => ValueFilter::Default(default.to_string()),
+        _ => return Err(err()),
+    };
+
+    Ok(filter)
+}
+
+/// Finds the first `pat` that's not inside quotes or parentheses.
+pub fn find_unquoted(input: &str, pat: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+
+    for (i, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, _) if depth == 0 && input[i..].starts_with(pat) => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Splits the input by `pat`, except inside quotes or parentheses.
+pub fn split_unquoted<'a>(mut input: &'a str, pat: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    while let Some(i) = find_unquoted(input, pat) {
+        parts.push(&input[..i]);
+        input = &input[i + pat.len()..];
+    }
+    parts.push(input);
+    parts
+}
+
+// strip the quotes of a string argument, like `"x"` or `'x'`
+fn unquote(arg: &str) -> &str {
+    ['"', '\'']
+        .into_iter()
+        .find_map(|q| arg.strip_prefix(q).and_then(|arg| arg.strip_suffix(q)))
+        .unwrap_or(arg)
+}
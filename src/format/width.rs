@@ -0,0 +1,133 @@
+use std::fmt;
+
+/// Fixed width of a field, set by the modifiers `<n`, `>n` and `^n`, like
+/// `{level:<5}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Width {
+    pub align: Align,
+    pub width: usize,
+    /// cut values wider than the width, ending with `…`, rather than writing
+    /// them as they are
+    pub truncate: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+impl Width {
+    /// Parses the width modifiers `<n`, `>n` and `^n`.
+    pub fn parse(input: &str) -> Option<Width> {
+        let align = match input.chars().next()? {
+            '<' => Align::Left,
+            '>' => Align::Right,
+            '^' => Align::Center,
+            _ => return None,
+        };
+        let width = input[1..].parse().ok()?;
+
+        Some(Width {
+            align,
+            width,
+            truncate: false,
+        })
+    }
+
+    /// Writes the formatted value padded or cut to the width; ANSI escape
+    /// codes of the value don't count towards the width.
+    pub fn write(&self, f: &mut impl fmt::Write, value: &str) -> fmt::Result {
+        let width = display_width(value);
+
+        if width > self.width {
+            if self.truncate {
+                write_truncated(f, value, self.width)?;
+            } else {
+                f.write_str(value)?;
+            }
+            return Ok(());
+        }
+
+        let pad = self.width - width;
+        let (left, right) = match self.align {
+            Align::Left => (0, pad),
+            Align::Right => (pad, 0),
+            Align::Center => (pad / 2, pad - pad / 2),
+        };
+
+        write!(f, "{:left$}{value}{:right$}", "", "")
+    }
+}
+
+/// Width of the text in terminal columns, skipping ANSI escape codes.
+pub fn display_width(s: &str) -> usize { visible_chars(s).map(|(_, c)| char_width(c)).sum() }
+
+// write the text cut to `width` columns with `…` at the end; escape codes are
+// kept, and the style is reset after the `…` if there were any
+fn write_truncated(f: &mut impl fmt::Write, value: &str, width: usize) -> fmt::Result {
+    if width == 0 {
+        return Ok(());
+    }
+
+    let mut end = value.len();
+    let mut written = 0;
+    for (i, c) in visible_chars(value) {
+        if written + char_width(c) > width - 1 {
+            end = i;
+            break;
+        }
+        written += char_width(c);
+    }
+
+    f.write_str(&value[..end])?;
+    f.write_char('…')?;
+    if value.contains('\x1b') {
+        f.write_str("\x1b[0m")?;
+    }
+
+    Ok(())
+}
+
+// chars of the text with their byte index, except for the ones in escape codes
+// like `\x1b[1;31m`
+fn visible_chars(s: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut chars = s.char_indices().peekable();
+
+    std::iter::from_fn(move || loop {
+        let (i, c) = chars.next()?;
+        if c != '\x1b' {
+            return Some((i, c));
+        }
+
+        // CSI sequences end with a char in '@'..='~'
+        if chars.next_if(|(_, c)| *c == '[').is_some() {
+            while chars.next_if(|(_, c)| !('@'..='~').contains(c)).is_some() {}
+            chars.next();
+        }
+    })
+}
+
+// columns taken by the char; wide east asian chars and emojis take two, and
+// combining marks and control chars take none
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0..=0x1f | 0x7f..=0x9f | 0x300..=0x36f | 0x200b..=0x200f | 0xfe00..=0xfe0f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
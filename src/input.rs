@@ -0,0 +1,480 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{Result, WrapErr};
+use flate2::bufread::MultiGzDecoder;
+
+use crate::{logfmt::parse_logfmt, Json, ParseError};
+
+/// Where the log lines are read from.
+#[derive(Debug)]
+pub enum Source {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Source {
+    /// Name of the source, as printed by `{@file}`. Stdin has no name.
+    pub fn name(&self) -> Option<String> {
+        match self {
+            Source::Stdin => None,
+            Source::File(path) => Some(path.display().to_string()),
+        }
+    }
+
+    /// Opens the source for reading. Gzip and zstd compressed files are
+    /// decompressed while being read.
+    pub fn open(&self) -> Result<Box<dyn BufRead>> {
+        match self {
+            Source::Stdin => Ok(Box::new(io::stdin().lock())),
+            Source::File(path) => {
+                let file = File::open(path)
+                    .wrap_err_with(|| format!("Failed to open file {}", path.display()))?;
+                let mut reader = BufReader::with_capacity(64 * 1024, file);
+
+                match Compression::detect(reader.fill_buf()?) {
+                    None => Ok(Box::new(reader)),
+                    Some(Compression::Gzip) => Ok(Box::new(BufReader::with_capacity(
+                        64 * 1024,
+                        MultiGzDecoder::new(reader),
+                    ))),
+                    Some(Compression::Zstd) => {
+                        let decoder = zstd::Decoder::with_buffer(reader).wrap_err_with(|| {
+                            format!("Failed to read zstd file {}", path.display())
+                        })?;
+                        Ok(Box::new(BufReader::with_capacity(64 * 1024, decoder)))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Format of the log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// JSON, or logfmt if the line is `key=value` pairs
+    #[default]
+    Auto,
+    Json,
+    /// `ts=... level=info msg="..." key=val`
+    Logfmt,
+}
+
+impl InputFormat {
+    /// Parses the line into `json`, reusing its allocations for JSON lines.
+    pub fn parse<'a>(self, json: &mut Json<'a>, input: &'a str) -> Result<(), ParseError> {
+        match self {
+            InputFormat::Json => json.parse_replace(input),
+            InputFormat::Logfmt => {
+                *json = parse_logfmt(input, true)?;
+                Ok(())
+            }
+            InputFormat::Auto => {
+                let trimmed = input.trim_start();
+                if trimmed.starts_with('{') || trimmed.starts_with('[') {
+                    return json.parse_replace(input);
+                }
+
+                // plain text lines are reported as invalid JSON
+                match parse_logfmt(input, false) {
+                    Ok(logfmt) => {
+                        *json = logfmt;
+                        Ok(())
+                    }
+                    Err(_) => json.parse_replace(input),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // sniff the compression format from the magic bytes at the start of a file
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns `true` if the file is gzip or zstd compressed.
+pub fn is_compressed(path: &Path) -> Result<bool> {
+    let mut file =
+        File::open(path).wrap_err_with(|| format!("Failed to open file {}", path.display()))?;
+
+    let mut header = [0; 4];
+    let mut len = 0;
+    while len < header.len() {
+        match file.read(&mut header[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    Ok(Compression::detect(&header[..len]).is_some())
+}
+
+/// Returns the sources to read from in order; stdin if no files are given.
+pub fn sources(files: Vec<PathBuf>) -> Vec<Source> {
+    if files.is_empty() {
+        vec![Source::Stdin]
+    } else {
+        files.into_iter().map(Source::File).collect()
+    }
+}
+
+/// Returns `true` if the input is piped or redirected into stdin, which is
+/// then safe to read without waiting on a terminal or a device left open.
+#[cfg(unix)]
+pub fn stdin_is_piped() -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    fs::metadata("/dev/stdin").is_ok_and(|metadata| {
+        let file_type = metadata.file_type();
+        file_type.is_fifo() || file_type.is_file()
+    })
+}
+
+#[cfg(not(unix))]
+pub fn stdin_is_piped() -> bool {
+    use std::io::IsTerminal;
+
+    !io::stdin().is_terminal()
+}
+
+/// How long to wait before checking a followed file for new data again.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long a followed file is idle before an unfinished multi-line record is
+/// printed as it is.
+pub const PENDING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A file being followed like `tail -F`: new lines are read as they are
+/// appended, and the file is reopened if it is truncated or rotated. A
+/// missing file is read once it is created.
+pub struct Followed {
+    path: PathBuf,
+    name: String,
+    reader: Option<BufReader<File>>,
+    id: Option<FileId>,
+    modified: Option<SystemTime>,
+    // bytes read from the current file
+    pos: u64,
+    // partially written line, kept until the rest of it is appended
+    pending: String,
+}
+
+impl Followed {
+    /// Opens the file positioned at the start of its last `tail` lines, or at
+    /// the end of the file if `tail` is not given.
+    pub fn open(path: PathBuf, tail: Option<usize>) -> Result<Self> {
+        let mut followed = Self {
+            name: path.display().to_string(),
+            path,
+            reader: None,
+            id: None,
+            modified: None,
+            pos: 0,
+            pending: String::new(),
+        };
+
+        let mut file = match File::open(&followed.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(followed),
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("Failed to open file {}", followed.name));
+            }
+        };
+        let metadata = file.metadata()?;
+        followed.id = file_id(&metadata);
+        followed.modified = metadata.modified().ok();
+        followed.pos = find_tail_start(&mut file, tail.unwrap_or(0))?;
+        file.seek(SeekFrom::Start(followed.pos))?;
+        followed.reader = Some(BufReader::with_capacity(64 * 1024, file));
+
+        Ok(followed)
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    /// Reads the next complete line into `line`.
+    ///
+    /// Returns `false` if no complete line has been appended yet.
+    pub fn read_line(&mut self, line: &mut String) -> Result<bool> {
+        loop {
+            // the file is checked for changes before more of it is read
+            let refill = self.reader.as_ref().is_none_or(|r| r.buffer().is_empty());
+            if refill && self.reopen_if_changed()? && !self.pending.is_empty() {
+                // the last line of the previous file will never be finished
+                std::mem::swap(line, &mut self.pending);
+                self.pending.clear();
+                return Ok(true);
+            }
+
+            let n = match &mut self.reader {
+                Some(reader) => reader.read_line(&mut self.pending)?,
+                None => 0,
+            };
+            self.pos += n as u64;
+
+            if self.pending.ends_with('\n') {
+                std::mem::swap(line, &mut self.pending);
+                self.pending.clear();
+                return Ok(true);
+            }
+
+            if n == 0 {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Checks whether the file was created, truncated or replaced since it was
+    // opened, and starts reading it from the beginning if so.
+    fn reopen_if_changed(&mut self) -> Result<bool> {
+        // file may be missing for a moment while being rotated
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(false);
+        };
+
+        let id = file_id(&metadata);
+        let modified = metadata.modified().ok();
+        let reader = match &mut self.reader {
+            Some(reader) if id == self.id => reader,
+            _ => {
+                let Ok(file) = File::open(&self.path) else {
+                    return Ok(false);
+                };
+                self.reader = Some(BufReader::with_capacity(64 * 1024, file));
+                self.id = id;
+                self.modified = modified;
+                self.pos = 0;
+                return Ok(true);
+            }
+        };
+
+        // a file rewritten in place may have grown past what was read, so the
+        // last byte read is compared once it was modified
+        let last = self.pending.as_bytes().last().copied().unwrap_or(b'\n');
+        let truncated = metadata.len() < self.pos
+            || (modified != self.modified && self.pos > 0 && {
+                let mut byte = [0];
+                reader.seek(SeekFrom::Start(self.pos - 1))?;
+                reader.read_exact(&mut byte)?;
+                byte[0] != last
+            });
+        self.modified = modified;
+
+        if truncated {
+            reader.seek(SeekFrom::Start(0))?;
+            self.pos = 0;
+            self.pending.clear();
+        }
+
+        Ok(truncated)
+    }
+}
+
+// Returns the offset where the last `lines` lines of the file start.
+fn find_tail_start(file: &mut File, lines: usize) -> io::Result<u64> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if lines == 0 {
+        return Ok(len);
+    }
+
+    let mut buf = [0; 8 * 1024];
+    let mut end = len;
+    let mut newlines = 0;
+
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+
+        for (i, b) in chunk.iter().enumerate().rev() {
+            let offset = start + i as u64;
+            // newline at the very end only terminates the last line
+            if *b == b'\n' && offset + 1 != len {
+                newlines += 1;
+                if newlines == lines {
+                    return Ok(offset + 1);
+                }
+            }
+        }
+
+        end = start;
+    }
+
+    Ok(0)
+}
+
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// rotation can't be detected without inodes; only truncation is handled
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<FileId> { None }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::OpenOptions, io::Write, process};
+
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    // file in the temp dir, unique to the test run
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("jlf-{}-{name}", process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn source_names() {
+        let names = |files: Vec<&str>| {
+            let files = files.into_iter().map(PathBuf::from).collect();
+            sources(files).iter().map(Source::name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(Vec::new()), [None]);
+        assert_eq!(names(vec!["a.log", "logs/b.log"]), [
+            Some("a.log".to_owned()),
+            Some("logs/b.log".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn compressed() {
+        let read = |path: &PathBuf| {
+            let mut text = String::new();
+            Source::File(path.clone())
+                .open()
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+
+        // concatenated gzip members, like from `cat a.gz b.gz`
+        let mut gzip = Vec::new();
+        for line in ["{\"a\":1}\n", "{\"b\":2}\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(line.as_bytes()).unwrap();
+            gzip.extend(encoder.finish().unwrap());
+        }
+        let path = temp_file("log.gz", &gzip);
+        assert!(is_compressed(&path).unwrap());
+        assert_eq!(read(&path), "{\"a\":1}\n{\"b\":2}\n");
+        fs::remove_file(path).unwrap();
+
+        let zstd = zstd::encode_all("{\"c\":3}\n".as_bytes(), 0).unwrap();
+        let path = temp_file("log.zst", &zstd);
+        assert!(is_compressed(&path).unwrap());
+        assert_eq!(read(&path), "{\"c\":3}\n");
+        fs::remove_file(path).unwrap();
+
+        // headers shorter than the magic bytes
+        let path = temp_file("short", b"{}");
+        assert!(!is_compressed(&path).unwrap());
+        assert_eq!(read(&path), "{}");
+        fs::remove_file(path).unwrap();
+        assert_eq!(Compression::detect(&[0x28, 0xb5]), None);
+        assert_eq!(Compression::detect(&[]), None);
+    }
+
+    #[test]
+    fn tail_start() {
+        let tail = |content: &str, lines: usize| {
+            let path = temp_file("tail", content.as_bytes());
+            let start = find_tail_start(&mut File::open(&path).unwrap(), lines).unwrap();
+            fs::remove_file(path).unwrap();
+            start
+        };
+
+        assert_eq!(tail("a\nb\nc\n", 0), 6);
+        assert_eq!(tail("a\nb\nc\n", 2), 2);
+        assert_eq!(tail("a\nb\nc\n", 10), 0);
+        assert_eq!(tail("a\nb\nc", 1), 4);
+        assert_eq!(tail("a\nb\nc", 2), 2);
+
+        // the last chunk starts in the middle of the long line
+        let long = format!("head\n{}\ny\n", "x".repeat(9000));
+        assert_eq!(tail(&long, 1), 9006);
+        assert_eq!(tail(&long, 2), 5);
+        assert_eq!(tail(&long, 3), 0);
+    }
+
+    #[test]
+    fn follow() {
+        let path = temp_file("follow", b"zero\none\n");
+        let append = |text: &str| {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+
+        let mut followed = Followed::open(path.clone(), Some(1)).unwrap();
+        let mut line = String::new();
+        assert!(followed.read_line(&mut line).unwrap());
+        assert_eq!(line, "one\n");
+        assert!(!followed.read_line(&mut line).unwrap());
+
+        // partial lines wait for the rest of them
+        append("tw");
+        assert!(!followed.read_line(&mut line).unwrap());
+        append("o\n");
+        assert!(followed.read_line(&mut line).unwrap());
+        assert_eq!(line, "two\n");
+
+        // truncated files are read from the start again
+        fs::write(&path, "new\n").unwrap();
+        assert!(followed.read_line(&mut line).unwrap());
+        assert_eq!(line, "new\n");
+        assert!(!followed.read_line(&mut line).unwrap());
+
+        // and so are files rewritten in place with more than was read
+        fs::write(&path, "rewritten\n").unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(1);
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(followed.read_line(&mut line).unwrap());
+        assert_eq!(line, "rewritten\n");
+
+        // missing files are read once they're created
+        fs::remove_file(&path).unwrap();
+        let mut followed = Followed::open(path.clone(), Some(1)).unwrap();
+        assert!(!followed.read_line(&mut line).unwrap());
+        fs::write(&path, "created\n").unwrap();
+        assert!(followed.read_line(&mut line).unwrap());
+        assert_eq!(line, "created\n");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use core::fmt;
-use std::iter::Peekable;
+use std::{borrow::Cow, iter::Peekable};
 
 use owo_colors::{
     colors::{Blue, BrightWhite, Green, White},
@@ -13,6 +13,12 @@ use smallvec::SmallVec;
 pub enum PathToken<'a> {
     Name(&'a str),
     Index(usize),
+    // index from the end, like `[-1]`
+    IndexFromEnd(usize),
+    // `[*]`, any key or index
+    Any,
+    // `..name`, the key at any depth
+    Descend(&'a str),
 }
 
 pub fn parse_json(input: &str) -> Result<Json<'_>, ParseError> {
@@ -21,6 +27,85 @@ pub fn parse_json(input: &str) -> Result<Json<'_>, ParseError> {
     Ok(json)
 }
 
+/// Unescapes the raw text of a JSON string, like `say \"hi\" \u00e9` ->
+/// `say "hi" é`. Invalid escapes are kept as they are.
+pub fn unescape(raw: &str) -> Cow<'_, str> { unescape_with(raw, true) }
+
+/// Unescapes the raw text of a JSON string like [`unescape`], but keeps the
+/// escapes of control characters, like `\n` or `\u001b`, as they are.
+pub fn unescape_printable(raw: &str) -> Cow<'_, str> { unescape_with(raw, false) }
+
+// control characters are only decoded with `control`
+fn unescape_with(raw: &str, control: bool) -> Cow<'_, str> {
+    if !raw.contains('\\') {
+        return Cow::Borrowed(raw);
+    }
+
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let (c, len) = match rest.as_bytes().get(1) {
+            Some(b'"') => ('"', 2),
+            Some(b'\\') => ('\\', 2),
+            Some(b'/') => ('/', 2),
+            Some(b'b') => ('\u{8}', 2),
+            Some(b'f') => ('\u{c}', 2),
+            Some(b'n') => ('\n', 2),
+            Some(b'r') => ('\r', 2),
+            Some(b't') => ('\t', 2),
+            Some(b'u') => match unescape_unicode(&rest[2..]) {
+                Some((c, len)) => (c, len + 2),
+                None => ('\\', 1),
+            },
+            _ => ('\\', 1),
+        };
+        if c.is_control() && !control {
+            out.push_str(&rest[..len]);
+        } else {
+            out.push(c);
+        }
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+
+    Cow::Owned(out)
+}
+
+/// Escapes the quotes and backslashes of the text to be the raw text of a
+/// JSON string, like `say "hi"` -> `say \"hi\"`; control characters are kept
+/// as they are. The reverse of [`unescape_printable`].
+pub fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['"', '\\']) {
+        return Cow::Borrowed(text);
+    }
+
+    Cow::Owned(text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// read the hex digits following `\u`, and the low surrogate following a high
+// one like `\ud83d\ude00`; returns the char and the length read
+fn unescape_unicode(s: &str) -> Option<(char, usize)> {
+    let hex = |s: &str| {
+        let hex = s.get(..4)?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        u16::from_str_radix(hex, 16).ok()
+    };
+
+    let high = hex(s)?;
+    if let Some(c) = char::from_u32(high as u32) {
+        return Some((c, 4));
+    }
+
+    let low = s[4..].strip_prefix("\\u").and_then(hex)?;
+    let c = char::decode_utf16([high, low]).next()?.ok()?;
+    Some((c, 10))
+}
+
 #[derive(Clone, Default)]
 pub enum Json<'a> {
     // first arg is the key value pairs, second is a list of keys used as
@@ -52,7 +137,7 @@ impl<'a> Json<'a> {
         Ok(())
     }
 
-    pub fn get(&self, key: &str) -> &Json {
+    pub fn get(&self, key: &str) -> &Json<'_> {
         match self {
             Json::Object(obj) => obj.get(key),
             _ => &Json::Null,
@@ -66,7 +151,7 @@ impl<'a> Json<'a> {
         }
     }
 
-    pub fn get_i(&self, index: usize) -> &Json {
+    pub fn get_i(&self, index: usize) -> &Json<'_> {
         match self {
             Json::Array(arr) => arr.get(index).unwrap_or(&Json::Null),
             _ => &Json::Null,
@@ -105,7 +190,7 @@ impl<'a> Json<'a> {
     /// `None` is returned.
     ///
     /// For more information read [RFC6901](https://tools.ietf.org/html/rfc6901).
-    pub fn pointer(&self, pointer: &str) -> Option<&Json> {
+    pub fn pointer(&self, pointer: &str) -> Option<&Json<'_>> {
         if pointer.is_empty() {
             return Some(self);
         }
@@ -214,6 +299,10 @@ impl<'a> Json<'a> {
         }
     }
 
+    /// Text of a string value with its escapes decoded, like `caf\u00e9` ->
+    /// `café`; borrowed if there's nothing to decode.
+    pub fn as_unescaped_str(&self) -> Option<Cow<'_, str>> { self.as_str().map(unescape) }
+
     pub fn as_value(&self) -> Option<&str> {
         match self {
             Json::Value(v) => Some(v),
@@ -224,6 +313,47 @@ impl<'a> Json<'a> {
     // Replace self with a new value and return the previous value
     pub fn replace(&mut self, value: Json<'a>) -> Json<'a> { std::mem::replace(self, value) }
 
+    /// Unescaped text of a string value holding a JSON object or array, like
+    /// `"{\"user\":1}"`.
+    pub fn nested_json(&self) -> Option<Cow<'_, str>> {
+        let s = self.as_str()?.trim();
+        let is_nested =
+            (s.starts_with('{') && s.ends_with('}')) || (s.starts_with('[') && s.ends_with(']'));
+        is_nested.then(|| unescape(s))
+    }
+
+    /// Collects the unescaped texts of the string values holding JSON, at any
+    /// depth, to be parsed by [`Self::with_nested`].
+    pub fn nested_texts(&self, texts: &mut Vec<String>) {
+        match self {
+            Json::Object(obj) => obj.iter().for_each(|(_, v)| v.nested_texts(texts)),
+            Json::Array(arr) => arr.iter().for_each(|v| v.nested_texts(texts)),
+            _ => texts.extend(self.nested_json().map(Cow::into_owned)),
+        }
+    }
+
+    /// Copy of the json with the string values holding JSON replaced by the
+    /// values parsed from `texts`, collected by [`Self::nested_texts`]. Strings
+    /// that fail to parse are kept as they are.
+    pub fn with_nested<'b>(&self, texts: &mut std::slice::Iter<'b, String>) -> Json<'b>
+    where
+        'a: 'b,
+    {
+        match self {
+            Json::Object(obj) => Json::Object(JsonObject(
+                obj.iter()
+                    .map(|(k, v)| (*k, v.with_nested(texts)))
+                    .collect(),
+            )),
+            Json::Array(arr) => Json::Array(arr.iter().map(|v| v.with_nested(texts)).collect()),
+            _ if self.nested_json().is_some() => texts
+                .next()
+                .and_then(|text| parse_json(text).ok())
+                .unwrap_or_else(|| self.clone()),
+            _ => self.clone(),
+        }
+    }
+
     fn parse_value_in_place<I>(
         &mut self,
         chars: &mut Peekable<I>,
@@ -323,13 +453,13 @@ impl<'a> Json<'a> {
 pub struct JsonObject<'a>(pub Vec<(&'a str, Json<'a>)>);
 
 impl<'a> JsonObject<'a> {
-    pub fn get(&self, key: &str) -> &Json { self.try_get(key).unwrap_or(&Json::Null) }
+    pub fn get(&self, key: &str) -> &Json<'_> { self.try_get(key).unwrap_or(&Json::Null) }
 
     pub fn get_mut<'b>(&'b mut self, key: &str) -> Option<&'b mut Json<'a>> {
         self.0.iter_mut().find(|(k, _)| k == &key).map(|(_, v)| v)
     }
 
-    pub fn try_get(&self, key: &str) -> Option<&Json> {
+    pub fn try_get(&self, key: &str) -> Option<&Json<'_>> {
         self.0.iter().find(|(k, _)| k == &key).map(|(_, v)| v)
     }
 
@@ -357,9 +487,9 @@ impl<'a> JsonObject<'a> {
         self.0.iter().all(|(_, v)| v.is_null())
     }
 
-    pub fn iter(&self) -> std::slice::Iter<(&'a str, Json<'a>)> { self.0.iter() }
+    pub fn iter(&self) -> std::slice::Iter<'_, (&'a str, Json<'a>)> { self.0.iter() }
 
-    pub fn iter_mut(&mut self) -> std::slice::IterMut<(&'a str, Json<'a>)> { self.0.iter_mut() }
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, (&'a str, Json<'a>)> { self.0.iter_mut() }
 
     pub fn parse_insert(&mut self, key: &'a str, input: &'a str) -> Result<(), ParseError> {
         if let Some((old_key, value)) = self.0.iter_mut().find(|(k, _)| k == &key) {
@@ -616,7 +746,7 @@ where
 }
 
 impl Json<'_> {
-    pub fn indented(&self, indent: usize) -> StyledJson {
+    pub fn indented(&self, indent: usize) -> StyledJson<'_> {
         StyledJson {
             json: self,
             indent,
@@ -624,7 +754,7 @@ impl Json<'_> {
         }
     }
 
-    pub fn styled(&self, styles: MarkupStyles) -> StyledJson {
+    pub fn styled(&self, styles: MarkupStyles) -> StyledJson<'_> {
         StyledJson {
             json: self,
             indent: 0,
@@ -875,14 +1005,16 @@ impl Json<'_> {
                 if !arr
                     .iter()
                     .enumerate()
-                    .any(|(i, value)| !value.is_null() && !is_excluded(excluded, |t| token_matches_index(t, i)))
+                    .any(|(i, value)| !value.is_null() && !is_excluded(excluded, |t| token_matches_index(t, i, arr.len())))
                 {
                     return write_syntax(f, "[]", styles);
                 }
                 write_syntax(f, "[", styles)?;
                 let mut first = true;
                 for (i, value) in arr.iter().enumerate() {
-                    if value.is_null() || is_excluded(excluded, |t| token_matches_index(t, i)) {
+                    if value.is_null()
+                        || is_excluded(excluded, |t| token_matches_index(t, i, arr.len()))
+                    {
                         continue;
                     }
                     if !first {
@@ -892,7 +1024,7 @@ impl Json<'_> {
                     if let Some(ind) = indent {
                         write!(f, "\n{:width$}", "", width = ind + 2)?;
                     }
-                    let child = child_excluded(excluded, |t| token_matches_index(t, i));
+                    let child = child_excluded(excluded, |t| token_matches_index(t, i, arr.len()));
                     write_rest_value(f, value, &child, indent, styles)?;
                 }
                 if let Some(ind) = indent {
@@ -924,9 +1056,10 @@ impl Json<'_> {
             }),
             Json::Array(arr) => arr.iter().enumerate().any(|(i, value)| {
                 !value.is_null()
-                    && !is_excluded(excluded, |t| token_matches_index(t, i))
+                    && !is_excluded(excluded, |t| token_matches_index(t, i, arr.len()))
                     && {
-                        let child = child_excluded(excluded, |t| token_matches_index(t, i));
+                        let child =
+                            child_excluded(excluded, |t| token_matches_index(t, i, arr.len()));
                         child.is_empty() || value.has_rest_content(&child)
                     }
             }),
@@ -964,23 +1097,38 @@ fn is_excluded(excluded: &[&[PathToken<'_>]], matches: impl Fn(&PathToken<'_>) -
 
 /// Collects the sub-paths of any excluded path that descends through this field
 /// (length > 1 with a matching first token), with that first token stripped.
+/// Paths starting with `..name` also apply to every child as they are.
 fn child_excluded<'p>(
     excluded: &[&'p [PathToken<'p>]],
     matches: impl Fn(&PathToken<'_>) -> bool,
 ) -> SmallVec<[&'p [PathToken<'p>]; 4]> {
-    excluded
-        .iter()
-        .filter(|p| p.len() > 1 && matches(&p[0]))
-        .map(|p| &p[1..])
-        .collect()
+    let mut child = SmallVec::new();
+    for p in excluded {
+        if p.len() > 1 && matches(&p[0]) {
+            child.push(&p[1..]);
+        }
+        if matches!(p[0], PathToken::Descend(_)) {
+            child.push(*p);
+        }
+    }
+    child
 }
 
 fn token_matches_key(tok: &PathToken<'_>, key: &str) -> bool {
-    matches!(tok, PathToken::Name(n) if *n == key)
+    match tok {
+        PathToken::Name(n) | PathToken::Descend(n) => *n == key,
+        PathToken::Any => true,
+        PathToken::Index(_) | PathToken::IndexFromEnd(_) => false,
+    }
 }
 
-fn token_matches_index(tok: &PathToken<'_>, index: usize) -> bool {
-    matches!(tok, PathToken::Index(i) if *i == index)
+fn token_matches_index(tok: &PathToken<'_>, index: usize, len: usize) -> bool {
+    match tok {
+        PathToken::Index(i) => *i == index,
+        PathToken::IndexFromEnd(n) => len.checked_sub(*n) == Some(index),
+        PathToken::Any => true,
+        PathToken::Name(_) | PathToken::Descend(_) => false,
+    }
 }
 
 fn write_key(
@@ -1105,6 +1253,33 @@ mod tests {
         assert_eq!(arr.get_i(2).get("obj").as_str(), Some("inside array"));
     }
 
+    #[test]
+    fn nested() {
+        assert_eq!(unescape(r#"say \"hi\"\n"#), "say \"hi\"\n");
+        assert_eq!(unescape(r"caf\u00e9 \ud83d\ude00"), "café 😀");
+        assert_eq!(unescape(r"bad \ud83d \x"), r"bad \ud83d \x");
+        assert_eq!(
+            unescape_printable(r#"\"a\"\/\u00e9\n\r\t\u001b[2m"#),
+            r#""a"/é\n\r\t\u001b[2m"#
+        );
+
+        let text = "at \"C:\\tmp\"\t\x1b[2m";
+        assert_eq!(escape(text), "at \\\"C:\\\\tmp\\\"\t\x1b[2m");
+        assert_eq!(unescape_printable(&escape(text)), text);
+        assert_eq!(unescape(&escape(text)), text);
+
+        let json =
+            parse_json(r#"{"body": "{\"user\": 1}", "list": ["[1, \"a\"]"], "text": "{x}"}"#)
+                .unwrap();
+        let mut texts = Vec::new();
+        json.nested_texts(&mut texts);
+        let expanded = json.with_nested(&mut texts.iter());
+
+        assert_eq!(expanded.get("body").get("user").as_value(), Some("1"));
+        assert_eq!(expanded.get("list").get_i(0).get_i(1).as_str(), Some("a"));
+        assert_eq!(expanded.get("text").as_str(), Some("{x}"));
+    }
+
     #[test]
     fn invalid() {
         let test_cases = vec![
@@ -0,0 +1,258 @@
+use std::{fmt, str::FromStr};
+
+use owo_colors::{AnsiColors, Style};
+use thiserror::Error;
+
+use crate::{
+    config::{LevelConfig, LevelsConfig},
+    format::parse::{parse_format, FormatError},
+    Json,
+};
+
+/// Severity of a log, ordered from the least to the most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    const ALL: [Level; 6] = [
+        Level::Trace,
+        Level::Debug,
+        Level::Info,
+        Level::Warn,
+        Level::Error,
+        Level::Fatal,
+    ];
+
+    /// Maps pino/bunyan numeric levels (10 = trace ... 60 = fatal) to a level.
+    pub fn from_number(n: f64) -> Self {
+        if n < 20.0 {
+            Level::Trace
+        } else if n < 30.0 {
+            Level::Debug
+        } else if n < 40.0 {
+            Level::Info
+        } else if n < 50.0 {
+            Level::Warn
+        } else if n < 60.0 {
+            Level::Error
+        } else {
+            Level::Fatal
+        }
+    }
+
+    fn as_upper(self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Fatal => "FATAL",
+        }
+    }
+
+    // style of the level when no style is configured
+    fn default_style(self, style: Style) -> Style {
+        match self {
+            Level::Trace => style.color(AnsiColors::Cyan).dimmed(),
+            Level::Debug => style.color(AnsiColors::Green),
+            Level::Info => style.color(AnsiColors::Cyan),
+            Level::Warn => style.color(AnsiColors::Yellow),
+            Level::Error => style.color(AnsiColors::Red),
+            Level::Fatal => style.color(AnsiColors::Red).bold(),
+        }
+    }
+}
+
+/// Vocabulary of the level field, and how each level is displayed by the
+/// `level` modifier; configured in the `[levels]` section of the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Levels {
+    // extra names (lowercase) and numeric ranges, checked before the built-in
+    // vocabulary
+    names: Vec<(String, Level)>,
+    ranges: Vec<(f64, f64, Level)>,
+    labels: [Option<String>; 6],
+    styles: [Option<Style>; 6],
+}
+
+impl Levels {
+    pub fn from_config(config: LevelsConfig) -> Result<Self, FormatError> {
+        let LevelsConfig {
+            trace,
+            debug,
+            info,
+            warn,
+            error,
+            fatal,
+        } = config;
+
+        let mut levels = Levels::default();
+        for (level, config) in Level::ALL
+            .into_iter()
+            .zip([trace, debug, info, warn, error, fatal])
+        {
+            let Some(LevelConfig {
+                names,
+                range,
+                label,
+                style,
+            }) = config
+            else {
+                continue;
+            };
+
+            for name in names {
+                levels.names.push((name.to_lowercase(), level));
+            }
+            if let Some([min, max]) = range {
+                levels.ranges.push((min, max, level));
+            }
+            levels.labels[level as usize] = label;
+            if let Some(style) = style {
+                levels.styles[level as usize] = parse_format(Some(&style), false, false)?.style;
+            }
+        }
+
+        Ok(levels)
+    }
+
+    /// Reads the level from a level field value, which may be a name like
+    /// `"WARN"` or `"warning"`, or a number like pino/bunyan's `40`.
+    pub fn parse(&self, json: &Json) -> Option<Level> {
+        if let Some(s) = json.as_str() {
+            let lower = s.trim().to_lowercase();
+            if let Some((_, level)) = self.names.iter().find(|(name, _)| *name == lower) {
+                return Some(*level);
+            }
+            // names like `inf` and `nan` are not numbers
+            if lower.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+                return lower.parse().ok().map(|n| self.parse_number(n));
+            }
+            lower.parse().ok()
+        } else if let Some(v) = json.as_value() {
+            v.parse::<f64>().ok().map(|n| self.parse_number(n))
+        } else {
+            None
+        }
+    }
+
+    fn parse_number(&self, n: f64) -> Level {
+        self.ranges
+            .iter()
+            .find(|(min, max, _)| *min <= n && n <= *max)
+            .map(|(_, _, level)| *level)
+            .unwrap_or_else(|| Level::from_number(n))
+    }
+
+    /// Text to display for the level; the configured label, or else the
+    /// original text of the field. Numeric levels are displayed by name.
+    pub fn label<'a>(&'a self, level: Level, json: &'a Json) -> &'a str {
+        if let Some(label) = &self.labels[level as usize] {
+            label
+        } else {
+            json.as_str().unwrap_or(level.as_upper())
+        }
+    }
+
+    /// Style of the level; the configured style, or else the default color of
+    /// the level applied on top of the field's `style`.
+    pub fn style(&self, level: Level, style: Style) -> Style {
+        self.styles[level as usize].unwrap_or_else(|| level.default_style(style))
+    }
+}
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let level = match input.trim().to_ascii_lowercase().as_str() {
+            "trace" | "trc" | "verbose" | "vrb" => Level::Trace,
+            "debug" | "dbg" => Level::Debug,
+            "info" | "inf" | "information" | "notice" => Level::Info,
+            "warn" | "wrn" | "warning" => Level::Warn,
+            "error" | "err" | "eror" => Level::Error,
+            "fatal" | "ftl" | "critical" | "crit" | "panic" | "alert" | "emergency" | "emerg" => {
+                Level::Fatal
+            }
+            s => match s.parse::<f64>() {
+                Ok(n) if s.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => {
+                    Level::from_number(n)
+                }
+                _ => {
+                    return Err(ParseLevelError {
+                        input: input.to_owned(),
+                    })
+                }
+            },
+        };
+
+        Ok(level)
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        };
+
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Failed to parse level: {input}")]
+pub struct ParseLevelError {
+    pub input: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_json;
+
+    #[test]
+    fn levels() {
+        let levels = Levels::from_config(LevelsConfig {
+            info: Some(LevelConfig {
+                names: vec!["I".to_owned()],
+                range: Some([1.0, 5.0]),
+                label: Some("INF".to_owned()),
+                style: Some("blue".to_owned()),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let json =
+            parse_json(r#"["i", 3, 40, "Warning", "verbose", "unknown", "INF", "nan"]"#).unwrap();
+        let parsed: Vec<_> = (0..8).map(|i| levels.parse(json.get_i(i))).collect();
+        assert_eq!(parsed, [
+            Some(Level::Info),
+            Some(Level::Info),
+            Some(Level::Warn),
+            Some(Level::Warn),
+            Some(Level::Trace),
+            None,
+            Some(Level::Info),
+            None
+        ]);
+
+        assert_eq!(levels.label(Level::Info, json.get_i(0)), "INF");
+        assert_eq!(levels.label(Level::Warn, json.get_i(2)), "WARN");
+        assert_eq!(levels.label(Level::Warn, json.get_i(3)), "Warning");
+    }
+}
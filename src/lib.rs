@@ -1,8 +1,22 @@
-use std::io::{self, BufRead, IsTerminal, Write};
+use std::{
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    thread,
+    time::Instant,
+};
 
+use assemble::{Assembler, Push};
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
-use config::ConfigFile;
+use color_eyre::eyre::{eyre, WrapErr};
+use config::{Config, ConfigFile, Profile};
+use filter::{Expr, Filter, LevelFilter, TimeFilter};
+use format::FieldPath;
+use input::{InputFormat, Source};
+use level::{Level, Levels};
 use owo_colors::OwoColorize;
+use prefix::{PrefixFormat, Record};
+use schema::Schema;
 
 pub mod colors;
 
@@ -12,8 +26,16 @@ pub use json::{parse_json, Json, ParseError};
 mod format;
 pub use format::{FormattedLog, Formatter};
 
+mod assemble;
 mod config;
 mod expand;
+mod filter;
+mod input;
+mod level;
+mod logfmt;
+mod prefix;
+mod schema;
+mod time;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -41,10 +63,104 @@ pub struct Args {
     #[arg(short = 's', long = "strict", default_value_t = false)]
     strict: bool,
 
+    /// Print lines that are not JSON, like stack frames, under the previous
+    /// record in the style of the variable `attached_fmt`.
+    #[arg(short = 'a', long = "attach-lines", default_value_t = false)]
+    attach_lines: bool,
+
+    /// Print lines that are not JSON in the style of the variable
+    /// `passthrough_fmt`, instead of as they are.
+    #[arg(long = "style-lines", default_value_t = false)]
+    style_lines: bool,
+
+    /// Print string values holding JSON, like `"{\"user\":1}"`, as nested
+    /// objects and arrays.
+    #[arg(long = "expand-nested", default_value_t = false)]
+    expand_nested: bool,
+
+    /// Format of the input lines; by default, lines of `key=value` pairs are
+    /// read as logfmt and the rest as JSON.
+    #[arg(
+        short = 'i',
+        long = "input",
+        value_name = "FORMAT",
+        default_value = "auto"
+    )]
+    input: InputFormat,
+
+    /// Format of the text before the records, like `[pod/api] {...}`, printed
+    /// by `{@prefix}`; `cri` and `docker` also read `{@time}` and `{@stream}`.
+    #[arg(long = "prefix", value_name = "FORMAT", default_value = "auto")]
+    prefix: PrefixFormat,
+
+    /// Join records spanning multiple lines, like pretty-printed JSON.
+    #[arg(short = 'm', long = "multiline", default_value_t = false)]
+    multiline: bool,
+
+    /// Print records spanning multiple lines as they are once they grow over
+    /// the size in bytes.
+    #[arg(
+        long = "max-record-size",
+        value_name = "BYTES",
+        default_value_t = 1024 * 1024,
+        requires = "multiline"
+    )]
+    max_record_size: usize,
+
     /// Take only the first N lines.
     #[arg(short = 't', long = "take")]
     take: Option<usize>,
 
+    /// Read logs from the file instead of stdin; can be passed multiple times.
+    #[arg(long = "file", value_name = "PATH")]
+    files: Vec<PathBuf>,
+
+    /// Only show logs at or above the level: trace, debug, info, warn, error or
+    /// fatal.
+    #[arg(long = "min-level", value_name = "LEVEL")]
+    min_level: Option<Level>,
+
+    /// Only show logs with one of the levels, separated by ','.
+    #[arg(long = "level", value_name = "LEVELS", value_delimiter = ',')]
+    levels: Vec<Level>,
+
+    /// Only show logs at or after the time; either a timestamp like
+    /// `2024-02-09T14:02:00Z`, a time of today like `14:02`, or a duration
+    /// before now like `10m` or `2h`.
+    #[arg(long = "since", value_name = "TIME", value_parser = time::parse_time_arg)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only show logs at or before the time, in the same format as `--since`.
+    #[arg(long = "until", value_name = "TIME", value_parser = time::parse_time_arg)]
+    until: Option<DateTime<Utc>>,
+
+    /// Only show logs matching the expression, like `status >= 500`; can be
+    /// passed multiple times.
+    #[arg(short = 'w', long = "where", value_name = "EXPR")]
+    wheres: Vec<String>,
+
+    /// Keep reading the files as new lines are appended, like `tail -F`.
+    #[arg(short = 'f', long = "follow", default_value_t = false)]
+    follow: bool,
+
+    /// When following files, start from the last N lines instead of the end.
+    #[arg(long = "tail", value_name = "N", requires = "follow")]
+    tail: Option<usize>,
+
+    /// Use the variables of the log format instead of detecting it from the
+    /// first record.
+    #[arg(long = "schema", value_name = "SCHEMA")]
+    schema: Option<Schema>,
+
+    /// Use the profile of the config file instead of selecting it by the first
+    /// record.
+    #[arg(short = 'p', long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Files to read logs from, passed after `--`.
+    #[arg(last = true, value_name = "PATH")]
+    paths: Vec<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -59,11 +175,19 @@ enum Command {
 
         #[command(flatten)]
         variables: Variables,
+
+        /// Files to detect the schema and the profile from, passed after `--`.
+        #[arg(last = true, value_name = "PATH")]
+        paths: Vec<PathBuf>,
     },
     /// List all variables
     List {
         #[command(flatten)]
         variables: Variables,
+
+        /// Files to detect the schema and the profile from, passed after `--`.
+        #[arg(last = true, value_name = "PATH")]
+        paths: Vec<PathBuf>,
     },
 }
 
@@ -84,44 +208,106 @@ pub fn run() -> Result<(), color_eyre::Report> {
         no_color,
         compact,
         strict,
+        attach_lines,
+        style_lines,
+        expand_nested,
+        input,
+        prefix,
+        multiline,
+        max_record_size,
         take,
+        min_level,
+        levels,
+        since,
+        until,
+        wheres,
+        mut files,
+        follow,
+        tail,
+        schema,
+        profile,
+        paths,
         command,
     } = Args::parse();
 
     let ConfigFile {
-        mut config,
+        config,
         variables: config_variables,
+        levels: levels_config,
+        profiles,
     } = config::get_config()?;
-    if let Some(format) = format {
-        config.format = Some(format);
-    }
-    if compact {
-        config.compact = Some(true);
-    }
-    if no_color {
-        config.no_color = Some(true);
-    }
-    if strict {
-        config.strict = Some(true);
+    // flags override the config files and the profile
+    let flags = Config {
+        format,
+        compact: compact.then_some(true),
+        no_color: no_color.then_some(true),
+        strict: strict.then_some(true),
+        expand_nested: expand_nested.then_some(true),
+        attach_lines: attach_lines.then_some(true),
+        style_lines: style_lines.then_some(true),
+    };
+
+    files.extend(paths);
+
+    let stdout = io::stdout();
+    let mut setup = Setup {
+        config,
+        flags,
+        config_variables,
+        variables: variables.variables,
+        levels: Levels::from_config(levels_config).wrap_err("Invalid [levels] config")?,
+        color: stdout.is_terminal() || color,
+        schema,
+        profile: None,
+        matchers: Vec::new(),
     }
-    let format = config.format.unwrap_or_else(|| "{&output}".to_owned());
-    let compact = config.compact.unwrap_or(false);
-    let no_color = config.no_color.unwrap_or(false);
-    let strict = config.strict.unwrap_or(false);
+    .with_profiles(profiles.unwrap_or_default(), profile)?;
+
+    if let Some(mut command) = command {
+        // subcommands take their own variables and files
+        let (variables, paths) = match &mut command {
+            Command::Expand { variables, paths, .. } | Command::List { variables, paths } => {
+                (variables.variables.take(), std::mem::take(paths))
+            }
+        };
+        setup.variables = variables;
+        files.extend(paths);
+
+        // detect the schema and the profile from the input, if any is given;
+        // stdin is only read if something is piped into it, so the
+        // subcommands don't wait on the terminal
+        let record = if setup.needs_detection() && (!files.is_empty() || input::stdin_is_piped()) {
+            first_record(files, input, prefix)?
+        } else {
+            None
+        };
+        let json = record.as_deref().map(parse_json).transpose()?;
+        let schema = setup.schema(json.as_ref());
+        let profile = setup.profile(json.as_ref());
 
-    if let Some(command) = command {
         match command {
-            Command::Expand {
-                variable,
-                variables: Variables { variables },
-            } => {
-                let variables = get_variables(config_variables, variables);
-                let format = variable.map(|e| format!("{{&{e}}}")).unwrap_or(format);
+            Command::Expand { variable, .. } => {
+                let profile = profile.map(|(_, profile)| profile);
+                let variables = setup.variables(schema, profile);
+                let format = match variable {
+                    Some(variable) => format!("{{&{variable}}}"),
+                    None => setup
+                        .config(profile)
+                        .format
+                        .unwrap_or_else(|| "{&output}".to_owned()),
+                };
 
                 println!("{}", expand::expanded_format(&format, &variables));
             }
-            Command::List { variables } => {
-                let variables = get_variables(config_variables, variables.variables);
+            Command::List { .. } => {
+                if let Some(schema) = schema {
+                    println!("# schema: {schema}");
+                }
+                if let Some((name, _)) = profile {
+                    println!("# profile: {name}");
+                }
+
+                let variables = setup.variables(schema, profile.map(|(_, profile)| profile));
                 let width = variables.iter().map(|(k, _)| k.len()).max().unwrap();
                 for (k, v) in variables {
                     println!("{:width$} = {v}", k.bold(), width = width);
@@ -132,95 +318,575 @@ pub fn run() -> Result<(), color_eyre::Report> {
         return Ok(());
     }
 
-    let stdin = io::stdin();
-    if !stdin.is_terminal() {
-        let stdout = io::stdout();
-        let no_color = no_color || (!stdout.is_terminal() && !color);
+    if follow && files.is_empty() {
+        return Err(eyre!("Following requires files to read from"));
+    }
+    if files.is_empty() && io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    let mut filter = Filter::default();
+    if min_level.is_some() || !levels.is_empty() {
+        filter.level = Some(LevelFilter {
+            field: FieldPath::default(),
+            min: min_level,
+            levels,
+            names: setup.levels.clone(),
+        });
+    }
+    if since.is_some() || until.is_some() {
+        filter.time = Some(TimeFilter {
+            field: FieldPath::default(),
+            since,
+            until,
+        });
+    }
+    for expr in wheres {
+        let expr = Expr::parse(&expr).wrap_err_with(|| format!("Invalid expression: {expr}"))?;
+        filter.expr = Some(match filter.expr.take() {
+            Some(prev) => Expr::And(Box::new(prev), Box::new(expr)),
+            None => expr,
+        });
+    }
+
+    let Resolved {
+        formatter,
+        attached,
+        passthrough,
+        no_color,
+        strict,
+        expand_nested,
+    } = setup.build(None, &mut filter)?;
 
+    let mut printer = Printer {
+        formatter,
+        attached,
+        passthrough,
+        shown: None,
+        filter,
+        // the schema and the profile not given are detected from the first
+        // record, which rebuilds the formatter
+        detect: setup.needs_detection().then_some(setup),
         // Buffer stdout: the formatter emits many small writes per record, and a
         // bare StdoutLock is line-buffered (a flush per '\n'). A BufWriter
         // collapses those into a few large writes.
-        let mut stdout = io::BufWriter::with_capacity(64 * 1024, stdout.lock());
+        stdout: io::BufWriter::with_capacity(64 * 1024, stdout.lock()),
+        out: String::new(),
+        no_color,
+        strict,
+        expand_nested,
+        input,
+        prefix,
+        assembler: multiline.then(|| Assembler::new(max_record_size)),
+        assembled_file: None,
+        take,
+        taken: 0,
+    };
 
-        let variables = get_variables(config_variables, variables.variables);
-        let expanded = expand::expanded_format(&format, &variables);
-        let formatter = Formatter::new(&expanded, no_color, compact)?;
+    // input line read from the source (allocation reused across iterations)
+    let mut line = String::new();
 
-        let mut buf = stdin.lock();
+    if follow {
+        let mut paths = Vec::with_capacity(files.len());
 
-        // input line read from stdin (allocation reused across iterations)
-        let mut line = String::new();
+        // compressed archives never grow, so they're read once before
+        // following the rest; missing files are followed once created
+        for path in files {
+            if path.exists() && input::is_compressed(&path)? {
+                if !print_source(&mut printer, &Source::File(path), &mut line)? {
+                    printer.stdout.flush()?;
+                    return Ok(());
+                }
+            } else {
+                paths.push(path);
+            }
+        }
 
-        // formatted output for one record (allocation reused across iterations)
-        let mut out = String::new();
+        let mut followed = paths
+            .into_iter()
+            .map(|path| input::Followed::open(path, tail))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        // how many lines have we taken?
-        let mut taken = 0;
+        let mut idle_since = None;
 
-        while buf.read_line(&mut line)? != 0 {
-            // Only run the (allocating) ANSI strip when the line actually
-            // contains an escape byte. JSON logs almost never do, so this skips
-            // a per-line allocation + full-line scan on the common path.
-            let stripped;
-            let input: &str = if line.as_bytes().contains(&0x1b) {
-                stripped = strip_ansi_escapes::strip_str(&line);
-                &stripped
+        'follow: loop {
+            let mut idle = true;
+
+            for file in &mut followed {
+                while file.read_line(&mut line)? {
+                    idle = false;
+
+                    let keep_going = printer.print_line(&line, Some(file.name()))?;
+                    line.clear();
+
+                    if !keep_going {
+                        break 'follow;
+                    }
+                }
+            }
+
+            // nothing new in any file; show what we have and wait for more. An
+            // unfinished multi-line record is shown as it is after a while, as
+            // the rest of it may never be written
+            if idle {
+                let since = *idle_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= input::PENDING_TIMEOUT && !printer.finish()? {
+                    break;
+                }
+                printer.stdout.flush()?;
+                thread::sleep(input::POLL_INTERVAL);
             } else {
-                &line
+                idle_since = None;
+            }
+        }
+    } else {
+        for source in input::sources(files) {
+            if !print_source(&mut printer, &source, &mut line)? {
+                break;
+            }
+        }
+    }
+
+    printer.stdout.flush()?;
+
+    Ok(())
+}
+
+/// Builds the formatter and the fields read by the filters from the config,
+/// the profile and the variables, which may depend on the first record.
+struct Setup {
+    config: Config,
+    // config given with flags
+    flags: Config,
+    config_variables: Option<Vec<(String, String)>>,
+    // variables given with `-v`
+    variables: Option<Vec<String>>,
+    levels: Levels,
+    // output is a terminal, or color is forced
+    color: bool,
+    // given with `--schema` and `--profile`
+    schema: Option<Schema>,
+    profile: Option<(String, Profile)>,
+    // profiles selected by matching the first record, in the order of the config
+    matchers: Vec<(Expr, (String, Profile))>,
+}
+
+/// Formatter and options resolved by [`Setup::build`].
+struct Resolved {
+    formatter: Formatter,
+    // formats the lines that are not records, if they are attached or styled
+    attached: Option<Formatter>,
+    passthrough: Option<Formatter>,
+    no_color: bool,
+    strict: bool,
+    expand_nested: bool,
+}
+
+impl Setup {
+    /// Sets the profile given with `--profile`; otherwise the profiles with a
+    /// matcher are selected by the first record.
+    fn with_profiles(
+        mut self,
+        mut profiles: Vec<(String, Profile)>,
+        name: Option<String>,
+    ) -> color_eyre::Result<Self> {
+        if let Some(name) = name {
+            let Some(i) = profiles.iter().position(|(k, _)| *k == name) else {
+                return Err(eyre!("Profile `{name}` not found in the config"));
             };
+            self.profile = Some(profiles.swap_remove(i));
+            return Ok(self);
+        }
 
-            if !input.trim().is_empty() {
-                // `json` is scoped to this iteration so its borrows of `input`
-                // end before the next read; this is what lets us avoid the
-                // previous lifetime-laundering `unsafe` block.
-                let mut json = Json::Null;
-                match json.parse_replace(input) {
-                    Ok(()) => {
-                        out.clear();
-                        formatter.as_log(&json).write_fmt(&mut out)?;
-                        out.push('\n');
-                        stdout.write_all(out.as_bytes())?;
-                    }
-                    Err(e) => {
-                        if strict {
-                            if no_color {
-                                writeln!(stdout, "{:?}", e)?;
-                            } else {
-                                writeln!(stdout, "{:?}", e.red())?;
-                            }
-                            stdout.flush()?;
-                            return Ok(());
-                        }
+        self.matchers = profiles
+            .into_iter()
+            .filter_map(|(name, profile)| {
+                let matcher = profile.matcher.clone()?;
+                let expr = Expr::parse(&matcher)
+                    .wrap_err_with(|| format!("Invalid match of profile `{name}`: {matcher}"));
+                Some(expr.map(|expr| (expr, (name, profile))))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self)
+    }
+
+    /// Returns `true` if the schema or the profile depends on the first record.
+    fn needs_detection(&self) -> bool {
+        self.schema.is_none() || (self.profile.is_none() && !self.matchers.is_empty())
+    }
+
+    fn schema(&self, json: Option<&Json>) -> Option<Schema> {
+        self.schema.or_else(|| json.and_then(Schema::detect))
+    }
+
+    fn profile(&self, json: Option<&Json>) -> Option<&(String, Profile)> {
+        self.profile.as_ref().or_else(|| {
+            let json = json?;
+            self.matchers
+                .iter()
+                .find_map(|(matcher, profile)| matcher.matches(json).then_some(profile))
+        })
+    }
+
+    fn config(&self, profile: Option<&Profile>) -> Config {
+        let mut config = self.config.clone();
+        if let Some(profile) = profile {
+            config.merge(profile.config.clone());
+        }
+        config.merge(self.flags.clone());
+        config
+    }
+
+    fn variables(
+        &self,
+        schema: Option<Schema>,
+        profile: Option<&Profile>,
+    ) -> Vec<(String, String)> {
+        let mut config_variables = self.config_variables.clone();
+        if let Some(profile) = profile {
+            config::merge_entries(&mut config_variables, profile.variables.clone());
+        }
+        get_variables(schema, config_variables, self.variables.clone())
+    }
+
+    fn build(&self, json: Option<&Json>, filter: &mut Filter) -> color_eyre::Result<Resolved> {
+        let profile = self.profile(json).map(|(_, profile)| profile);
+        let config = self.config(profile);
+        let variables = self.variables(self.schema(json), profile);
+
+        if let Some(level) = &mut filter.level {
+            level.field = FieldPath::parse(&expand::expanded_format("{&level}", &variables))?;
+            if level.field.is_empty() {
+                return Err(eyre!("Filtering by level requires the variable `level`"));
+            }
+        }
+        if let Some(time) = &mut filter.time {
+            time.field = FieldPath::parse(&expand::expanded_format("{&timestamp}", &variables))?;
+            if time.field.is_empty() {
+                return Err(eyre!("Filtering by time requires the variable `timestamp`"));
+            }
+        }
+
+        let format = config.format.as_deref().unwrap_or("{&output}");
+        let compact = config.compact.unwrap_or(false);
+        let no_color = config.no_color.unwrap_or(false) || !self.color;
+        let expanded = expand::expanded_format(format, &variables);
+        let formatter =
+            Formatter::new(&expanded, no_color, compact)?.with_levels(self.levels.clone());
+        // formatters of the lines that are not records, if enabled
+        let line_formatter = |enabled: Option<bool>, variable| -> color_eyre::Result<_> {
+            if !enabled.unwrap_or(false) {
+                return Ok(None);
+            }
+            let expanded = expand::expanded_format(variable, &variables);
+            let formatter = Formatter::new(&expanded, no_color, compact)?;
+            Ok(Some(formatter.with_levels(self.levels.clone())))
+        };
+        let attached = line_formatter(config.attach_lines, "{&attached_fmt}")?;
+        let passthrough = line_formatter(config.style_lines, "{&passthrough_fmt}")?;
+
+        Ok(Resolved {
+            formatter,
+            attached,
+            passthrough,
+            no_color,
+            strict: config.strict.unwrap_or(false),
+            expand_nested: config.expand_nested.unwrap_or(false),
+        })
+    }
+}
 
-                        // not strict: echo the line unchanged (already includes
-                        // its trailing newline from read_line)
-                        if no_color {
-                            stdout.write_all(input.as_bytes())?;
-                        } else {
-                            stdout.write_all(line.as_bytes())?;
+/// Returns the first record of the sources as JSON, without its prefix.
+fn first_record(
+    files: Vec<PathBuf>,
+    format: InputFormat,
+    prefix: PrefixFormat,
+) -> color_eyre::Result<Option<String>> {
+    let mut line = String::new();
+
+    for source in input::sources(files) {
+        let mut buf = source.open()?;
+        while buf.read_line(&mut line)? != 0 {
+            let input = strip_ansi_escapes::strip_str(&line);
+            let (_, record) = prefix.split(input.trim());
+            let text;
+            let mut json = Json::Null;
+            let parsed = match record {
+                Record::Parsed(record) => {
+                    json = record;
+                    Ok(())
+                }
+                Record::Text(record) => {
+                    text = record;
+                    match prefix {
+                        PrefixFormat::Auto => {
+                            prefix::parse_auto(&text, format, &mut json).map(|_| ())
                         }
+                        _ => format.parse(&mut json, &text),
                     }
                 }
+            };
+            if parsed.is_ok() {
+                return Ok(Some(json.to_string()));
             }
-
             line.clear();
+        }
+    }
 
-            // take only N lines if specified
-            if let Some(take) = take.as_ref() {
-                taken += 1;
-                if taken >= *take {
-                    break;
+    Ok(None)
+}
+
+/// Prints every line of the source until its end.
+///
+/// Returns `false` if no more lines should be printed.
+fn print_source<W: Write>(
+    printer: &mut Printer<W>,
+    source: &Source,
+    line: &mut String,
+) -> color_eyre::Result<bool> {
+    let file = source.name();
+    let mut buf = source.open()?;
+
+    while buf
+        .read_line(line)
+        .wrap_err_with(|| format!("Failed to read {}", file.as_deref().unwrap_or("stdin")))?
+        != 0
+    {
+        let keep_going = printer.print_line(line, file.as_deref())?;
+        line.clear();
+
+        if !keep_going {
+            return Ok(false);
+        }
+    }
+
+    printer.finish()
+}
+
+/// Formats input lines and writes them to the output.
+struct Printer<W: Write> {
+    formatter: Formatter,
+    // formats the lines that are not records under the previous record, or
+    // by themselves
+    attached: Option<Formatter>,
+    passthrough: Option<Formatter>,
+    // whether the previous record was shown, or `None` before the first one
+    shown: Option<bool>,
+    filter: Filter,
+    // rebuilds the formatter once the first record is read
+    detect: Option<Setup>,
+    stdout: W,
+    // formatted output for one record (allocation reused across iterations)
+    out: String,
+    no_color: bool,
+    strict: bool,
+    // parse string values holding JSON in every record
+    expand_nested: bool,
+    input: InputFormat,
+    prefix: PrefixFormat,
+    // joins the lines of multi-line records, and the file they are read from
+    assembler: Option<Assembler>,
+    assembled_file: Option<String>,
+    // take only N lines if specified
+    take: Option<usize>,
+    // how many lines have we taken?
+    taken: usize,
+}
+
+impl<W: Write> Printer<W> {
+    /// Formats a single line read from `file` and writes it to the output;
+    /// lines of a multi-line record are kept until the record is complete.
+    ///
+    /// Returns `false` if no more lines should be printed, either because the
+    /// line was invalid in strict mode or enough lines were taken.
+    fn print_line(&mut self, line: &str, file: Option<&str>) -> color_eyre::Result<bool> {
+        let Some(assembler) = &self.assembler else {
+            return self.print_record(line, file);
+        };
+
+        // records don't continue into another file
+        if assembler.is_pending() && self.assembled_file.as_deref() != file && !self.finish()? {
+            return Ok(false);
+        }
+
+        let push = self.assembler.as_mut().map_or(Push::Line, |a| a.push(line));
+        match push {
+            Push::Line => self.print_record(line, file),
+            Push::Pending => {
+                if self.assembled_file.as_deref() != file {
+                    self.assembled_file = file.map(str::to_owned);
+                }
+                Ok(true)
+            }
+            Push::Record(record) => self.print_record(&record, file),
+            Push::Abandoned(lines) => {
+                // the kept lines are printed as plain lines, and the line may
+                // start another record
+                self.assembled_file = None;
+                for kept in lines.split_inclusive('\n') {
+                    if !self.print_record(kept, file)? {
+                        return Ok(false);
+                    }
                 }
+                self.print_line(line, file)
             }
         }
+    }
 
-        stdout.flush()?;
+    /// Prints the lines of the unfinished multi-line record, if any, as they
+    /// are.
+    ///
+    /// Returns `false` if no more lines should be printed.
+    fn finish(&mut self) -> color_eyre::Result<bool> {
+        let Some(record) = self.assembler.as_mut().and_then(Assembler::take) else {
+            return Ok(true);
+        };
+        let file = self.assembled_file.take();
+        self.print_record(&record, file.as_deref())
     }
 
-    Ok(())
+    /// Formats a single record, which may span multiple lines.
+    fn print_record(&mut self, line: &str, file: Option<&str>) -> color_eyre::Result<bool> {
+        // Only run the (allocating) ANSI strip when the line actually
+        // contains an escape byte. JSON logs almost never do, so this skips
+        // a per-line allocation + full-line scan on the common path.
+        let stripped;
+        let input: &str = if line.as_bytes().contains(&0x1b) {
+            stripped = strip_ansi_escapes::strip_str(line);
+            &stripped
+        } else {
+            line
+        };
+
+        if !input.trim().is_empty() && !self.write_input(line, input, file)? {
+            return Ok(false);
+        }
+
+        if let Some(take) = self.take {
+            self.taken += 1;
+            if self.taken >= take {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Returns `false` if the input was invalid in strict mode.
+    fn write_input(
+        &mut self,
+        line: &str,
+        input: &str,
+        file: Option<&str>,
+    ) -> color_eyre::Result<bool> {
+        let (mut prefix, record) = self.prefix.split(input);
+        // docker records are unescaped from their wrapper, and must outlive
+        // the record
+        let text;
+        // `json` is scoped to this call so its borrows of `input` end before
+        // the next read; this is what lets us avoid the previous
+        // lifetime-laundering `unsafe` block.
+        let mut json = Json::Null;
+        let parsed = match record {
+            Record::Parsed(record) => {
+                json = record;
+                Ok(())
+            }
+            Record::Text(record) => {
+                text = record;
+                match self.prefix {
+                    PrefixFormat::Auto => prefix::parse_auto(&text, self.input, &mut json)
+                        .map(|text| prefix.text = text),
+                    _ => self.input.parse(&mut json, &text),
+                }
+            }
+        };
+
+        match parsed {
+            Ok(()) => {
+                if let Some(setup) = self.detect.take() {
+                    let resolved = setup.build(Some(&json), &mut self.filter)?;
+                    self.formatter = resolved.formatter;
+                    self.attached = resolved.attached;
+                    self.passthrough = resolved.passthrough;
+                    self.no_color = resolved.no_color;
+                    self.strict = resolved.strict;
+                    self.expand_nested = resolved.expand_nested;
+                }
+
+                // the nested JSON is parsed from the unescaped texts, which
+                // must outlive the expanded record
+                let mut texts = Vec::new();
+                let expanded;
+                let json = if self.expand_nested {
+                    json.nested_texts(&mut texts);
+                    expanded = json.with_nested(&mut texts.iter());
+                    &expanded
+                } else {
+                    &json
+                };
+
+                self.shown = Some(self.filter.matches(json));
+                if self.shown == Some(false) {
+                    return Ok(true);
+                }
+
+                self.out.clear();
+                self.formatter
+                    .as_log(json)
+                    .with_file(file)
+                    .with_prefix(prefix)
+                    .write_fmt(&mut self.out)?;
+                self.out.push('\n');
+                self.stdout.write_all(self.out.as_bytes())?;
+            }
+            Err(e) => {
+                if self.strict {
+                    if self.no_color {
+                        writeln!(self.stdout, "{:?}", e)?;
+                    } else {
+                        writeln!(self.stdout, "{:?}", e.red())?;
+                    }
+                    self.stdout.flush()?;
+                    return Ok(false);
+                }
+
+                let formatter = match (&self.attached, self.shown) {
+                    // hidden with the record they belong to
+                    (Some(_), Some(false)) => return Ok(true),
+                    (Some(attached), Some(true)) => Some(attached),
+                    _ => self.passthrough.as_ref(),
+                };
+                if let Some(formatter) = formatter {
+                    let text = json::escape(input.trim_end_matches(['\n', '\r']));
+                    self.out.clear();
+                    formatter
+                        .as_log(&Json::Null)
+                        .with_file(file)
+                        .with_line(&text)
+                        .write_fmt(&mut self.out)?;
+                    self.out.push('\n');
+                    self.stdout.write_all(self.out.as_bytes())?;
+                    return Ok(true);
+                }
+
+                // not strict: echo the line unchanged (already includes
+                // its trailing newline from read_line)
+                if self.no_color {
+                    self.stdout.write_all(input.as_bytes())?;
+                } else {
+                    self.stdout.write_all(line.as_bytes())?;
+                }
+            }
+        }
+
+        Ok(true)
+    }
 }
 
 fn get_variables(
+    schema: Option<Schema>,
     from_config: Option<Vec<(String, String)>>,
     args: Option<Vec<String>>,
 ) -> Vec<(String, String)> {
@@ -232,7 +898,11 @@ fn get_variables(
         ("log".to_owned(), "{&timestamp|&level|&message}".to_owned()),
         (
             "log_fmt".to_owned(),
-            "{&timestamp_fmt}{&level_fmt}{&message_fmt}".to_owned(),
+            "{&prefix_fmt}{&timestamp_fmt}{&level_fmt}{&message_fmt}{&stack_fmt}".to_owned(),
+        ),
+        (
+            "prefix_fmt".to_owned(),
+            "{#key @prefix}{@prefix:dimmed} {/key}".to_owned(),
         ),
         (
             "timestamp_fmt".to_owned(),
@@ -241,7 +911,7 @@ fn get_variables(
         ("timestamp".to_owned(), "{timestamp}".to_owned()),
         (
             "level_fmt".to_owned(),
-            "{#key &level}{&level:level} {/key}".to_owned(),
+            "{#key &level}{&level:level,>5} {/key}".to_owned(),
         ),
         ("level".to_owned(), "{level|lvl|severity}".to_owned()),
         ("message_fmt".to_owned(), "{&message}".to_owned()),
@@ -249,14 +919,39 @@ fn get_variables(
             "message".to_owned(),
             "{message|msg|body|fields.message}".to_owned(),
         ),
+        (
+            "stack_fmt".to_owned(),
+            r#"{#key &stack}{#config compact}{:else}\n{&stack:multiline,indent=2,dimmed}{/config}{/key}"#
+                .to_owned(),
+        ),
+        (
+            "stack".to_owned(),
+            "{stack|stacktrace|stack_trace|backtrace|error.stack|err.stack}".to_owned(),
+        ),
         (
             "new_line".to_owned(),
             r#"{#key &data}{#config compact} {:else}\n{/config}{/key}"#.to_owned(),
         ),
         ("data_fmt".to_owned(), "{&data:json}".to_owned()),
         ("data".to_owned(), "{..}".to_owned()),
+        (
+            "attached_fmt".to_owned(),
+            "{@line:indent=2,dimmed}".to_owned(),
+        ),
+        (
+            "passthrough_fmt".to_owned(),
+            "{@line:level_words}".to_owned(),
+        ),
     ];
 
+    if let Some(schema) = schema {
+        for (k2, v2) in schema.variables() {
+            if let Some((_, v)) = variables.iter_mut().find(|(k, _)| k == k2) {
+                *v = schema::with_defaults(v2, v);
+            }
+        }
+    }
+
     if let Some(from_config) = from_config {
         for (k2, v2) in from_config {
             let v = variables
@@ -286,3 +981,187 @@ fn get_variables(
 
     variables
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [config]
+        strict = true
+
+        [variables]
+        message = "{text}"
+
+        [profiles.api]
+        match = 'service == "api"'
+        config = { format = "{&message} {&level}", compact = true }
+        variables = { message = "{api_msg}", level = "{api_level}" }
+
+        [profiles.any]
+        match = "service exists"
+        variables = { message = "{any_msg}" }
+
+        [profiles.manual]
+        config = { strict = false }
+    "#;
+
+    fn load_setup(
+        config_file: &str,
+        profile: Option<&str>,
+        flags: Config,
+    ) -> color_eyre::Result<Setup> {
+        let ConfigFile {
+            config,
+            variables,
+            levels,
+            profiles,
+        } = toml::from_str(config_file)?;
+
+        Setup {
+            config,
+            flags,
+            config_variables: variables,
+            variables: None,
+            levels: Levels::from_config(levels)?,
+            color: false,
+            schema: None,
+            profile: None,
+            matchers: Vec::new(),
+        }
+        .with_profiles(profiles.unwrap_or_default(), profile.map(str::to_owned))
+    }
+
+    fn variable<'a>(variables: &'a [(String, String)], key: &str) -> &'a str {
+        variables.iter().find(|(k, _)| k == key).unwrap().1.as_str()
+    }
+
+    #[test]
+    fn profiles() {
+        let api =
+            parse_json(r#"{"service": "api", "api_msg": "hi", "api_level": "warn"}"#).unwrap();
+        let worker = parse_json(r#"{"service": "worker"}"#).unwrap();
+        let other = parse_json(r#"{"msg": "hi"}"#).unwrap();
+
+        // the first profile matching the record, in the order of the config
+        let mut setup = load_setup(CONFIG, None, Config::default()).unwrap();
+        let name = |json| setup.profile(Some(json)).map(|(name, _)| name.as_str());
+        assert_eq!(name(&api), Some("api"));
+        assert_eq!(name(&worker), Some("any"));
+        assert_eq!(name(&other), None);
+
+        // defaults < schema < config < profile < `-v`
+        let profile = setup
+            .profile(Some(&api))
+            .map(|(_, profile)| profile.clone());
+        let profile = profile.as_ref();
+        let variables = setup.variables(Some(Schema::Zap), None);
+        assert_eq!(variable(&variables, "timestamp"), "{ts|timestamp}");
+        assert_eq!(variable(&variables, "level"), "{level|lvl|severity}");
+        assert_eq!(variable(&variables, "message"), "{text}");
+        let variables = setup.variables(Some(Schema::Zap), profile);
+        assert_eq!(variable(&variables, "level"), "{api_level}");
+        assert_eq!(variable(&variables, "message"), "{api_msg}");
+        setup.variables = Some(vec!["message={cli_msg}".to_owned()]);
+        let variables = setup.variables(Some(Schema::Zap), profile);
+        assert_eq!(variable(&variables, "message"), "{cli_msg}");
+
+        // config < profile < flags
+        let config = setup.config(profile);
+        assert_eq!((config.compact, config.strict), (Some(true), Some(true)));
+        setup.variables = None;
+        let resolved = setup.build(Some(&api), &mut Filter::default()).unwrap();
+        assert_eq!(resolved.formatter.as_log(&api).to_string(), "hi warn");
+        assert!(resolved.strict);
+
+        // `--profile` is used for every record
+        let setup = load_setup(CONFIG, Some("manual"), Config {
+            compact: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(setup.matchers.is_empty());
+        let (name, profile) = setup.profile(Some(&api)).unwrap();
+        assert_eq!(name, "manual");
+        let config = setup.config(Some(profile));
+        assert_eq!((config.compact, config.strict), (Some(true), Some(false)));
+
+        assert!(load_setup(CONFIG, Some("missing"), Config::default()).is_err());
+    }
+
+    // printer writing to a buffer, with the formatters of the setup
+    fn printer(setup: &Setup, mut filter: Filter) -> Printer<Vec<u8>> {
+        let Resolved {
+            formatter,
+            attached,
+            passthrough,
+            no_color,
+            strict,
+            expand_nested,
+        } = setup.build(None, &mut filter).unwrap();
+
+        Printer {
+            formatter,
+            attached,
+            passthrough,
+            shown: None,
+            filter,
+            detect: None,
+            stdout: Vec::new(),
+            out: String::new(),
+            no_color,
+            strict,
+            expand_nested,
+            input: InputFormat::Auto,
+            prefix: PrefixFormat::Auto,
+            assembler: None,
+            assembled_file: None,
+            take: None,
+            taken: 0,
+        }
+    }
+
+    #[test]
+    fn attached_lines() {
+        let flags = Config {
+            format: Some("{&level}: {&message}".to_owned()),
+            attach_lines: Some(true),
+            ..Default::default()
+        };
+        let setup = load_setup("", None, flags).unwrap();
+        let variables = setup.variables(None, None);
+        assert_eq!(
+            variable(&variables, "attached_fmt"),
+            "{@line:indent=2,dimmed}"
+        );
+
+        let filter = Filter {
+            level: Some(LevelFilter {
+                field: FieldPath::default(),
+                min: Some(Level::Warn),
+                levels: Vec::new(),
+                names: Levels::default(),
+            }),
+            ..Default::default()
+        };
+        let mut printer = printer(&setup, filter);
+        let lines = [
+            "starting\n",
+            r#"{"level": "error", "message": "failed"}"#,
+            "\tat main.rs:10\n",
+            "\tat lib.rs:20\n",
+            r#"{"level": "info", "message": "retrying"}"#,
+            "\tat retry.rs:30\n",
+            r#"{"level": "warn", "message": "slow"}"#,
+        ];
+        for line in lines {
+            assert!(printer.print_line(line, None).unwrap());
+        }
+
+        let output = String::from_utf8(printer.stdout).unwrap();
+        assert_eq!(
+            output,
+            "starting\nerror: failed\n  \tat main.rs:10\n  \tat lib.rs:20\nwarn: slow\n"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    json::{JsonObject, ParseError},
+    Json,
+};
+
+/// Parses a logfmt line, like `level=info msg="hi there" port=80`, into an
+/// object, borrowing the keys and values from the input like JSON records.
+///
+/// Quoted values keep their escapes, numbers and booleans become values, and
+/// keys without `=` are `true`, unless `bare_keys` is `false`, where they are
+/// an error instead; it's used to tell logfmt from plain text.
+pub fn parse_logfmt(input: &str, bare_keys: bool) -> Result<Json<'_>, ParseError> {
+    let err = |message, index| ParseError {
+        message,
+        value: input.to_owned(),
+        index,
+    };
+
+    let mut object = JsonObject::default();
+    let mut chars = input.char_indices().peekable();
+
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let Some(&(start, _)) = chars.peek() else {
+            break;
+        };
+
+        let mut end = input.len();
+        let mut has_value = false;
+        while let Some((i, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+            match c {
+                '=' => {
+                    end = i;
+                    has_value = true;
+                    break;
+                }
+                '"' => return Err(err("Unexpected quote in logfmt key", i)),
+                _ => {}
+            }
+        }
+        if !has_value {
+            end = chars.peek().map_or(input.len(), |&(i, _)| i);
+        }
+
+        let key = &input[start..end];
+        if key.is_empty() {
+            return Err(err("Expected key before `=`", start));
+        }
+        if !has_value {
+            if !bare_keys || object.0.is_empty() {
+                return Err(err("Expected `=` after logfmt key", end));
+            }
+            object.insert(key, Json::Value("true"));
+            continue;
+        }
+
+        let value = match chars.peek() {
+            Some(&(open, '"')) => {
+                chars.next();
+                let mut close = None;
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            close = Some(i);
+                            break;
+                        }
+                        '\\' => {
+                            chars.next();
+                        }
+                        _ => {}
+                    }
+                }
+                let close = close.ok_or_else(|| err("Closing quote not found for value", open))?;
+                if chars.peek().is_some_and(|(_, c)| !c.is_whitespace()) {
+                    return Err(err("Expected whitespace after quoted value", close + 1));
+                }
+                Json::String(&input[open + 1..close])
+            }
+            Some(&(value_start, _)) => {
+                let mut value_end = input.len();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        value_end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+                unquoted(&input[value_start..value_end])
+            }
+            None => Json::String(""),
+        };
+
+        object.insert(key, value);
+    }
+
+    if object.0.is_empty() {
+        return Err(err("Expected logfmt `key=value` pairs", 0));
+    }
+
+    Ok(Json::Object(object))
+}
+
+// numbers, booleans and null are values like in JSON, and the rest are strings
+fn unquoted(value: &str) -> Json<'_> {
+    match value {
+        "null" => Json::Null,
+        "true" | "false" => Json::Value(value),
+        _ if value.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+            && value.parse::<f64>().is_ok_and(f64::is_finite) =>
+        {
+            Json::Value(value)
+        }
+        _ => Json::String(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let input = r#"ts=2024-02-09T07:22:41Z level=info msg="hi \"there\"" port=80 ok cached=false empty="" x="#;
+        let json = parse_logfmt(input, true).unwrap();
+
+        assert_eq!(json.get("ts").as_str(), Some("2024-02-09T07:22:41Z"));
+        assert_eq!(json.get("msg").as_str(), Some(r#"hi \"there\""#));
+        assert_eq!(json.get("port").as_value(), Some("80"));
+        assert_eq!(json.get("ok").as_value(), Some("true"));
+        assert_eq!(json.get("cached").as_value(), Some("false"));
+        assert_eq!(json.get("empty").as_str(), Some(""));
+        assert_eq!(json.get("x").as_str(), Some(""));
+
+        assert!(parse_logfmt(input, false).is_err());
+        assert!(parse_logfmt("Starting server on port=80", true).is_err());
+        assert!(parse_logfmt(r#"msg="unclosed"#, true).is_err());
+        assert!(parse_logfmt(r#"msg="v"rest level=info"#, true).is_err());
+        assert!(parse_logfmt("", true).is_err());
+    }
+}
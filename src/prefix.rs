@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+
+use crate::{input::InputFormat, json::unescape, parse_json, Json, ParseError};
+
+/// How the text before the record of a line is read, like the
+/// `2024-02-09T07:22:41Z stdout F ` of container runtimes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PrefixFormat {
+    /// Any text before the first `{` starting a JSON object, if the line is
+    /// not a record itself
+    #[default]
+    Auto,
+    /// `2024-02-09T07:22:41.439Z stdout F {...}` of containerd and CRI-O
+    Cri,
+    /// `{"log":"{...}\n","stream":"stdout","time":"..."}` of docker's
+    /// json-file driver
+    Docker,
+    /// Lines are records as they are
+    None,
+}
+
+/// Text before the record, as printed by `{@prefix}`, and the parts of it
+/// read by the built-in formats.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Prefix<'a> {
+    pub text: Option<&'a str>,
+    pub time: Option<&'a str>,
+    pub stream: Option<&'a str>,
+}
+
+/// Record of a line split from its prefix.
+#[derive(Debug)]
+pub enum Record<'a> {
+    /// Text of the record, to be parsed in the input format.
+    Text(Cow<'a, str>),
+    /// The line itself, already parsed while looking for the prefix.
+    Parsed(Json<'a>),
+}
+
+impl PrefixFormat {
+    /// Splits the line into the prefix and the record of the built-in
+    /// formats; docker records are unescaped from their wrapper.
+    ///
+    /// Other lines are returned as they are; the prefix of `Auto` is split
+    /// while parsing, by [`parse_auto`].
+    pub fn split(self, line: &str) -> (Prefix<'_>, Record<'_>) {
+        let parts = match self {
+            PrefixFormat::Cri => split_cri(line),
+            PrefixFormat::Docker => split_docker(line),
+            PrefixFormat::Auto | PrefixFormat::None => None,
+        };
+
+        parts.unwrap_or_else(|| (Prefix::default(), Record::Text(Cow::Borrowed(line))))
+    }
+}
+
+/// Parses the record of the line into `json`, splitting off any text before
+/// it if the line is not a record itself, like `[pod/api-7f9] {"level":...}`;
+/// returns the text.
+pub fn parse_auto<'a>(
+    line: &'a str,
+    format: InputFormat,
+    json: &mut Json<'a>,
+) -> Result<Option<&'a str>, ParseError> {
+    // `[pod/api-7f9] {...}` would be read as an array
+    if line.trim_start().starts_with('[') {
+        if let Some((text, record)) = find_record(line) {
+            *json = record;
+            return Ok(Some(text));
+        }
+    }
+
+    match format.parse(json, line) {
+        Ok(()) => Ok(None),
+        Err(e) => {
+            let (text, record) = find_record(line).ok_or(e)?;
+            *json = record;
+            Ok(Some(text))
+        }
+    }
+}
+
+// the first `{` after some text starting a JSON object that ends the line;
+// only a `{` after a space or a delimiter like `]` or `:` is tried, so long
+// lines of text are not parsed again at every brace
+fn find_record(line: &str) -> Option<(&str, Json<'_>)> {
+    let line = line.trim();
+    if !line.ends_with('}') {
+        return None;
+    }
+
+    line.char_indices()
+        .skip(1)
+        .filter(|&(i, c)| {
+            c == '{'
+                && line[..i].ends_with(|c: char| {
+                    c.is_whitespace() || matches!(c, ']' | ')' | ':' | '|' | '>')
+                })
+        })
+        .find_map(|(i, _)| Some((line[..i].trim(), parse_json(&line[i..]).ok()?)))
+}
+
+// `<time> <stream> <F|P> <log>`; partial lines, tagged `P`, are printed as
+// they are split
+fn split_cri(line: &str) -> Option<(Prefix<'_>, Record<'_>)> {
+    let mut parts = line.splitn(4, ' ');
+    let time = parts.next()?;
+    let stream = parts.next().filter(|s| matches!(*s, "stdout" | "stderr"))?;
+    parts.next().filter(|tag| tag.starts_with(['F', 'P']))?;
+    let log = parts.next()?;
+
+    let prefix = Prefix {
+        text: Some(line[..line.len() - log.len()].trim_end()),
+        time: Some(time),
+        stream: Some(stream),
+    };
+    Some((prefix, Record::Text(Cow::Borrowed(log))))
+}
+
+// the record is the escaped string of `log`; other records are kept parsed
+fn split_docker(line: &str) -> Option<(Prefix<'_>, Record<'_>)> {
+    let json = parse_json(line).ok()?;
+    let mut prefix = Prefix::default();
+    let mut log = None;
+
+    let Some(object) = json.as_object() else {
+        return Some((prefix, Record::Parsed(json)));
+    };
+    for (key, value) in object.iter() {
+        let Json::String(value) = value else {
+            continue;
+        };
+        match *key {
+            "log" => log = Some(*value),
+            "time" => prefix.time = Some(*value),
+            "stream" => prefix.stream = Some(*value),
+            _ => {}
+        }
+    }
+
+    let Some(log) = log else {
+        return Some((Prefix::default(), Record::Parsed(json)));
+    };
+    let log = match unescape(log) {
+        Cow::Borrowed(log) => Cow::Borrowed(log.trim_end()),
+        Cow::Owned(log) => Cow::Owned(log.trim_end().to_owned()),
+    };
+    Some((prefix, Record::Text(log)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes() {
+        let record_text = |record| match record {
+            Record::Text(text) => text,
+            Record::Parsed(json) => panic!("parsed {json}"),
+        };
+
+        let mut json = Json::Null;
+        let line = r#"[pod/api-7f9] {"msg": "{hi}"}"#;
+        let text = parse_auto(line, InputFormat::Auto, &mut json).unwrap();
+        assert_eq!(text, Some("[pod/api-7f9]"));
+        assert_eq!(json.get("msg").as_str(), Some("{hi}"));
+
+        let line = r#"app:{"msg": "hi"}"#;
+        assert_eq!(
+            parse_auto(line, InputFormat::Auto, &mut json).unwrap(),
+            Some("app:")
+        );
+        assert!(parse_auto(r#"a{"msg": "hi"}"#, InputFormat::Auto, &mut json).is_err());
+
+        let line = r#"[{"msg": "hi"}]"#;
+        assert_eq!(
+            parse_auto(line, InputFormat::Auto, &mut json).unwrap(),
+            None
+        );
+        assert!(json.is_array());
+
+        let line = r#"msg=hi data={"a":1}"#;
+        assert_eq!(
+            parse_auto(line, InputFormat::Auto, &mut json).unwrap(),
+            None
+        );
+        assert!(parse_auto("no {record} here", InputFormat::Auto, &mut json).is_err());
+
+        let cri = r#"2024-02-09T07:22:41.439Z stderr F {"msg": "hi"}"#;
+        let (prefix, record) = PrefixFormat::Cri.split(cri);
+        assert_eq!(prefix.text, Some("2024-02-09T07:22:41.439Z stderr F"));
+        assert_eq!(prefix.time, Some("2024-02-09T07:22:41.439Z"));
+        assert_eq!(prefix.stream, Some("stderr"));
+        assert_eq!(record_text(record), r#"{"msg": "hi"}"#);
+
+        let docker =
+            r#"{"log":"{\"msg\":\"hi\"}\n","stream":"stdout","time":"2024-02-09T07:22:41Z"}"#;
+        let (prefix, record) = PrefixFormat::Docker.split(docker);
+        assert_eq!(prefix.text, None);
+        assert_eq!(prefix.time, Some("2024-02-09T07:22:41Z"));
+        assert_eq!(prefix.stream, Some("stdout"));
+        assert_eq!(record_text(record), r#"{"msg":"hi"}"#);
+
+        // lines without the wrapper are only parsed once
+        let (_, record) = PrefixFormat::Docker.split(r#"{"msg": "hi"}"#);
+        let Record::Parsed(json) = record else {
+            panic!("not parsed: {record:?}");
+        };
+        assert_eq!(json.get("msg").as_str(), Some("hi"));
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::Json;
+
+/// Common structured log formats, each with a preset of variables telling
+/// where the timestamp, level and message of a record are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Schema {
+    /// `{"level":30,"time":1707463361439,"msg":"..."}`
+    Pino,
+    /// `{"v":0,"level":30,"time":"2024-02-09T07:22:41.439Z","msg":"..."}`
+    Bunyan,
+    /// `{"level":"info","ts":1707463361.439,"caller":"...","msg":"..."}`
+    Zap,
+    /// `{"level":"info","time":"2024-02-09T07:22:41Z","msg":"..."}`
+    Logrus,
+    /// `{"@t":"2024-02-09T07:22:41.439Z","@mt":"...","@l":"Warning"}`
+    Serilog,
+    /// `{"timestamp":"...","level":"INFO","fields":{"message":"..."}}`
+    Tracing,
+    /// `{"severity":"INFO","timestamp":"...","jsonPayload":{"message":"..."}}`
+    Gcp,
+    /// `{"@timestamp":"...","log.level":"info","ecs.version":"1.6.0"}`
+    Ecs,
+}
+
+impl Schema {
+    /// Guesses the schema from the keys of a record.
+    pub fn detect(json: &Json) -> Option<Schema> {
+        let has = |key: &str| !json.get(key).is_null();
+        let is_number = |key: &str| {
+            json.get(key)
+                .as_value()
+                .is_some_and(|v| v.parse::<f64>().is_ok())
+        };
+
+        // most specific keys are checked first
+        let schema = if has("@t") && (has("@mt") || has("@m")) {
+            Schema::Serilog
+        } else if has("@timestamp") && (has("log.level") || has("ecs.version") || has("ecs")) {
+            Schema::Ecs
+        } else if has("severity")
+            && (has("jsonPayload") || has("textPayload") || has("logging.googleapis.com/trace"))
+        {
+            Schema::Gcp
+        } else if has("v") && is_number("level") && has("msg") {
+            Schema::Bunyan
+        } else if is_number("level") && is_number("time") {
+            Schema::Pino
+        } else if has("target") && json.get("fields").is_object() {
+            Schema::Tracing
+        } else if has("ts") && has("level") && (has("msg") || has("caller")) {
+            Schema::Zap
+        } else if has("time") && has("level") && has("msg") {
+            Schema::Logrus
+        } else {
+            return None;
+        };
+
+        Some(schema)
+    }
+
+    /// Fields of the schema, tried before the default fields of the
+    /// variables.
+    pub fn variables(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Schema::Pino | Schema::Bunyan | Schema::Logrus => &[
+                ("timestamp", "{time}"),
+                ("level", "{level}"),
+                ("message", "{msg}"),
+            ],
+            Schema::Zap => &[
+                ("timestamp", "{ts}"),
+                ("level", "{level}"),
+                ("message", "{msg}"),
+            ],
+            Schema::Serilog => &[
+                ("timestamp", "{@t}"),
+                ("level", "{@l}"),
+                ("message", "{@m|@mt}"),
+            ],
+            Schema::Tracing => &[
+                ("timestamp", "{timestamp}"),
+                ("level", "{level}"),
+                ("message", "{fields.message}"),
+            ],
+            Schema::Gcp => &[
+                ("timestamp", "{timestamp|time}"),
+                ("level", "{severity}"),
+                ("message", "{message|jsonPayload.message|textPayload}"),
+            ],
+            Schema::Ecs => &[
+                ("timestamp", "{@timestamp}"),
+                ("level", r#"{["log.level"]|log.level}"#),
+                ("message", "{message}"),
+            ],
+        }
+    }
+}
+
+/// Joins the fields of the schema with the default fields of the variable,
+/// like `{@l}` and `{level|lvl|severity}` into `{@l|level|lvl|severity}`, so
+/// records of other formats in the same stream are still read.
+pub fn with_defaults<'a>(fields: &'a str, defaults: &'a str) -> String {
+    let inner = |field: &'a str| {
+        field
+            .strip_prefix('{')
+            .and_then(|field| field.strip_suffix('}'))
+            .unwrap_or(field)
+    };
+
+    let mut options: Vec<_> = inner(fields).split('|').collect();
+    for option in inner(defaults).split('|') {
+        if !options.contains(&option) {
+            options.push(option);
+        }
+    }
+
+    format!("{{{}}}", options.join("|"))
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Schema::Pino => "pino",
+            Schema::Bunyan => "bunyan",
+            Schema::Zap => "zap",
+            Schema::Logrus => "logrus",
+            Schema::Serilog => "serilog",
+            Schema::Tracing => "tracing",
+            Schema::Gcp => "gcp",
+            Schema::Ecs => "ecs",
+        };
+
+        f.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_json;
+
+    #[test]
+    fn detect() {
+        let cases = [
+            (
+                r#"{"level":30,"time":1707463361439,"pid":1,"hostname":"h","msg":"hi"}"#,
+                Some(Schema::Pino),
+            ),
+            (
+                r#"{"name":"app","hostname":"h","pid":1,"level":30,"msg":"hi","time":"2024-02-09T07:22:41.439Z","v":0}"#,
+                Some(Schema::Bunyan),
+            ),
+            (
+                r#"{"level":"info","ts":1707463361.439,"caller":"main.go:12","msg":"hi"}"#,
+                Some(Schema::Zap),
+            ),
+            (
+                r#"{"level":"info","msg":"hi","time":"2024-02-09T07:22:41Z"}"#,
+                Some(Schema::Logrus),
+            ),
+            (
+                r#"{"@t":"2024-02-09T07:22:41.439Z","@mt":"Hello {User}","User":"a"}"#,
+                Some(Schema::Serilog),
+            ),
+            (
+                r#"{"timestamp":"2024-02-09T07:22:41.439Z","level":"INFO","fields":{"message":"hi"},"target":"app"}"#,
+                Some(Schema::Tracing),
+            ),
+            (
+                r#"{"severity":"ERROR","jsonPayload":{"message":"hi"},"timestamp":"2024-02-09T07:22:41Z"}"#,
+                Some(Schema::Gcp),
+            ),
+            (
+                r#"{"@timestamp":"2024-02-09T07:22:41.439Z","log.level":"info","message":"hi","ecs.version":"1.6.0"}"#,
+                Some(Schema::Ecs),
+            ),
+            (r#"{"timestamp":"2024-02-09","message":"hi"}"#, None),
+        ];
+
+        for (input, expected) in cases {
+            let json = parse_json(input).unwrap();
+            assert_eq!(Schema::detect(&json), expected, "{input}");
+        }
+    }
+    #[test]
+    fn with_defaults() {
+        let cases = [
+            ("{@l}", "{level|lvl|severity}", "{@l|level|lvl|severity}"),
+            ("{level}", "{level|lvl|severity}", "{level|lvl|severity}"),
+            ("{ts}", "{timestamp}", "{ts|timestamp}"),
+            (
+                "{message|jsonPayload.message|textPayload}",
+                "{message|msg|body|fields.message}",
+                "{message|jsonPayload.message|textPayload|msg|body|fields.message}",
+            ),
+        ];
+
+        for (fields, defaults, expected) in cases {
+            assert_eq!(super::with_defaults(fields, defaults), expected);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::cell::Cell;
+
+use chrono::{
+    format::StrftimeItems, DateTime, Duration, FixedOffset, Local, LocalResult, NaiveDate,
+    NaiveDateTime, NaiveTime, SecondsFormat, TimeZone, Utc,
+};
+use thiserror::Error;
+
+use crate::Json;
+
+// formats of timestamps without a timezone, which are taken as local time
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Reads the time from a timestamp field value.
+///
+/// Strings may be RFC 3339 timestamps, or ISO timestamps without a timezone
+/// which are taken as local time. Numbers are unix epochs, in seconds,
+/// milliseconds, microseconds or nanoseconds depending on their magnitude.
+pub fn parse_timestamp(json: &Json) -> Option<DateTime<FixedOffset>> {
+    if let Some(s) = json.as_str() {
+        parse_datetime(s).or_else(|| parse_epoch(s))
+    } else if let Some(v) = json.as_value() {
+        parse_epoch(v)
+    } else {
+        None
+    }
+}
+
+fn parse_datetime(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t);
+    }
+
+    NAIVE_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .and_then(from_local)
+}
+
+fn parse_epoch(s: &str) -> Option<DateTime<FixedOffset>> {
+    let s = s.trim();
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    let int: i64 = int.parse().ok()?;
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    // guess the unit from the magnitude; how many digits to shift to nanoseconds
+    let abs = int.unsigned_abs();
+    let unit_digits = if abs < 100_000_000_000 {
+        9
+    } else if abs < 100_000_000_000_000 {
+        6
+    } else if abs < 100_000_000_000_000_000 {
+        3
+    } else {
+        0
+    };
+
+    let mut nanos = int.checked_mul(10_i64.pow(unit_digits))?;
+    if unit_digits > 0 && !frac.is_empty() {
+        let frac = &frac[..frac.len().min(unit_digits as usize)];
+        let frac_nanos = frac.parse::<i64>().ok()? * 10_i64.pow(unit_digits - frac.len() as u32);
+        nanos += if s.starts_with('-') { -frac_nanos } else { frac_nanos };
+    }
+
+    Some(DateTime::from_timestamp_nanos(nanos).fixed_offset())
+}
+
+fn from_local(t: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    match Local.from_local_datetime(&t) {
+        LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => Some(t.fixed_offset()),
+        LocalResult::None => None,
+    }
+}
+
+/// Parses the time given to `--since` and `--until`.
+///
+/// Accepts RFC 3339 timestamps, local date and times like `2024-02-09
+/// 14:02`, times of today like `14:02`, and durations before now like `10m`
+/// or `1h30m`.
+pub fn parse_time_arg(input: &str) -> Result<DateTime<Utc>, ParseTimeError> {
+    let s = input.trim();
+    let err = || ParseTimeError {
+        input: input.to_owned(),
+    };
+
+    if let Some(t) = parse_datetime(s) {
+        return Ok(t.to_utc());
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let t = from_local(date.and_time(NaiveTime::MIN)).ok_or_else(err)?;
+        return Ok(t.to_utc());
+    }
+
+    if let Some(time) = ["%H:%M:%S%.f", "%H:%M"]
+        .iter()
+        .find_map(|f| NaiveTime::parse_from_str(s, f).ok())
+    {
+        let t = from_local(Local::now().date_naive().and_time(time)).ok_or_else(err)?;
+        return Ok(t.to_utc());
+    }
+
+    let duration = parse_duration(s).ok_or_else(err)?;
+    Utc::now().checked_sub_signed(duration).ok_or_else(err)
+}
+
+// parse durations like `30s`, `10m`, `1h30m`, `2d` or `500ms`; `None` if
+// they overflow
+fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total = Duration::zero();
+    let mut rest = s;
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+        let n: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let duration = match &rest[..unit] {
+            "ms" => Duration::try_milliseconds(n),
+            "s" => Duration::try_seconds(n),
+            "m" => Duration::try_minutes(n),
+            "h" => Duration::try_hours(n),
+            "d" => Duration::try_days(n),
+            "w" => Duration::try_weeks(n),
+            _ => return None,
+        }?;
+        rest = &rest[unit..];
+
+        total = total.checked_add(&duration)?;
+    }
+
+    Some(total)
+}
+
+/// How a timestamp field is displayed, set by the modifiers `time(..)`,
+/// `local`, `utc`, `since_start` and `delta`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TimeFormat {
+    /// strftime-like format, like `%H:%M:%S%.3f`
+    pub format: Option<String>,
+    pub zone: Option<Zone>,
+    pub relative: Option<Relative>,
+    // times of the first and the previous log, for relative times
+    start: Cell<Option<DateTime<FixedOffset>>>,
+    prev: Cell<Option<DateTime<FixedOffset>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    Local,
+    Utc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relative {
+    /// time since the first log
+    SinceStart,
+    /// time since the previous log
+    Delta,
+}
+
+impl TimeFormat {
+    /// Returns `true` if the strftime-like format is valid.
+    pub fn is_valid_format(format: &str) -> bool { StrftimeItems::new(format).parse().is_ok() }
+
+    /// Formats the timestamp field value, or returns `None` if it's not a
+    /// timestamp.
+    pub fn format(&self, json: &Json) -> Option<String> {
+        let t = parse_timestamp(json)?;
+
+        if let Some(relative) = self.relative {
+            let start = self.start.get().unwrap_or(t);
+            self.start.set(Some(start));
+            let prev = self.prev.replace(Some(t)).unwrap_or(t);
+            let since = match relative {
+                Relative::SinceStart => start,
+                Relative::Delta => prev,
+            };
+            return Some(format_duration(t - since));
+        }
+
+        let formatted = match (self.zone, &self.format) {
+            (Some(Zone::Local), Some(f)) => t.with_timezone(&Local).format(f).to_string(),
+            (Some(Zone::Utc), Some(f)) => t.to_utc().format(f).to_string(),
+            (None, Some(f)) => t.format(f).to_string(),
+            (Some(Zone::Local), None) => t
+                .with_timezone(&Local)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            (Some(Zone::Utc), None) => t.to_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            (None, None) => t.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        };
+
+        Some(formatted)
+    }
+}
+
+// format durations like `+0.250s`, `+1m02.000s` or `-1h00m05.100s`
+fn format_duration(d: Duration) -> String {
+    let sign = if d < Duration::zero() { '-' } else { '+' };
+    let millis = d.num_milliseconds().unsigned_abs();
+    let (h, m, s, ms) = (
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
+    );
+
+    if h > 0 {
+        format!("{sign}{h}h{m:02}m{s:02}.{ms:03}s")
+    } else if m > 0 {
+        format!("{sign}{m}m{s:02}.{ms:03}s")
+    } else {
+        format!("{sign}{s}.{ms:03}s")
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Failed to parse time: {input}")]
+pub struct ParseTimeError {
+    pub input: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_json;
+
+    #[test]
+    fn timestamps() {
+        let expected = DateTime::parse_from_rfc3339("2024-02-09T07:22:41.439Z")
+            .unwrap()
+            .to_utc();
+
+        let json = parse_json(
+            r#"{"iso": "2024-02-09T07:22:41.439Z", "offset": "2024-02-09T09:22:41.439+02:00", "secs": 1707463361.439, "millis": 1707463361439, "micros": "1707463361439000", "nanos": 1707463361439000000}"#,
+        )
+        .unwrap();
+
+        for key in ["iso", "offset", "secs", "millis", "micros", "nanos"] {
+            let t = parse_timestamp(json.get(key)).unwrap();
+            assert_eq!(t.timestamp_millis(), expected.timestamp_millis(), "{key}");
+        }
+
+        let naive = parse_json(r#"{"t": "2024-02-09T07:22:41.439284"}"#).unwrap();
+        assert!(parse_timestamp(naive.get("t")).is_some());
+    }
+
+    #[test]
+    fn time_formats() {
+        let time = TimeFormat {
+            format: Some("%H:%M:%S%.3f".to_owned()),
+            zone: Some(Zone::Utc),
+            ..Default::default()
+        };
+        let json = parse_json(r#"{"t": "2024-02-09T09:22:41.439284+02:00"}"#).unwrap();
+        assert_eq!(time.format(json.get("t")).as_deref(), Some("07:22:41.439"));
+
+        let delta = TimeFormat {
+            relative: Some(Relative::Delta),
+            ..Default::default()
+        };
+        let json = parse_json(r#"[1707463361000, 1707463361250, 1707463423250]"#).unwrap();
+        let formatted: Vec<_> = (0..3).filter_map(|i| delta.format(json.get_i(i))).collect();
+        assert_eq!(formatted, ["+0.000s", "+0.250s", "+1m02.000s"]);
+
+        let since_start = TimeFormat {
+            relative: Some(Relative::SinceStart),
+            ..Default::default()
+        };
+        let formatted: Vec<_> = (0..3)
+            .filter_map(|i| since_start.format(json.get_i(i)))
+            .collect();
+        assert_eq!(formatted, ["+0.000s", "+0.250s", "+1m02.250s"]);
+    }
+
+    #[test]
+    fn time_args() {
+        assert!(parse_time_arg("2024-02-09T07:22:41Z").is_ok());
+        assert!(parse_time_arg("2024-02-09").is_ok());
+        assert!(parse_time_arg("14:02").is_ok());
+        assert!(parse_time_arg("yesterday").is_err());
+
+        assert_eq!(parse_duration("1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::milliseconds(500)));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("m"), None);
+
+        // overflows are errors
+        assert_eq!(parse_duration("999999999999999d"), None);
+        assert_eq!(parse_duration("9223372036854775807ms1w"), None);
+        assert!(parse_time_arg("99999999999d").is_err());
+    }
+}